@@ -26,9 +26,15 @@
 #[macro_use]
 mod macros;
 
+use crate::registers::capability::{
+    CapabilityParameters1, StructuralParameters1, StructuralParameters2,
+};
+use crate::registers::operational::PageSizeRegister;
+use crate::ring::transfer::Ring;
 use bit_field::BitField;
 use core::convert::TryInto;
 use core::fmt;
+use core::ptr;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
@@ -310,6 +316,219 @@ pub trait DeviceHandler {
     fn endpoint_mut(&mut self, dci: usize) -> &mut dyn EndpointHandler;
 }
 
+/// Input Context whose size is selected at runtime.
+///
+/// Use this type instead of [`Input32Byte`] or [`Input64Byte`] if the Context Size is known only
+/// after reading the Capability Parameters 1 register.
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum AnyInput {
+    /// 32 byte Input Context.
+    Byte32(Input32Byte),
+    /// 64 byte Input Context.
+    Byte64(Input64Byte),
+}
+impl AnyInput {
+    /// Creates an empty Input Context whose size is indicated by the Context Size bit of
+    /// `hccparams1`.
+    #[must_use]
+    pub fn new(hccparams1: CapabilityParameters1) -> Self {
+        if hccparams1.context_size() {
+            Self::Byte64(Input64Byte::new_64byte())
+        } else {
+            Self::Byte32(Input32Byte::new_32byte())
+        }
+    }
+
+    /// Reinterprets `buf` as an Input Context whose size is indicated by the Context Size bit of
+    /// `hccparams1`.
+    ///
+    /// This is useful to build an Input Context directly in the memory accessible by the xHC.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if `buf` is not 4-byte aligned, or if the length of `buf` is
+    /// not the same as the size of the Input Context.
+    pub fn from_bytes_mut(
+        buf: &mut [u8],
+        hccparams1: CapabilityParameters1,
+    ) -> Result<&mut dyn InputHandler, InvalidBuffer> {
+        if hccparams1.context_size() {
+            Ok(reinterpret_mut::<Input64Byte>(buf)?)
+        } else {
+            Ok(reinterpret_mut::<Input32Byte>(buf)?)
+        }
+    }
+}
+impl InputHandler for AnyInput {
+    fn control(&self) -> &dyn InputControlHandler {
+        match self {
+            Self::Byte32(i) => i.control(),
+            Self::Byte64(i) => i.control(),
+        }
+    }
+
+    fn control_mut(&mut self) -> &mut dyn InputControlHandler {
+        match self {
+            Self::Byte32(i) => i.control_mut(),
+            Self::Byte64(i) => i.control_mut(),
+        }
+    }
+
+    fn device(&self) -> &dyn DeviceHandler {
+        match self {
+            Self::Byte32(i) => i.device(),
+            Self::Byte64(i) => i.device(),
+        }
+    }
+
+    fn device_mut(&mut self) -> &mut dyn DeviceHandler {
+        match self {
+            Self::Byte32(i) => i.device_mut(),
+            Self::Byte64(i) => i.device_mut(),
+        }
+    }
+}
+
+/// Device Context whose size is selected at runtime.
+///
+/// Use this type instead of [`Device32Byte`] or [`Device64Byte`] if the Context Size is known only
+/// after reading the Capability Parameters 1 register.
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum AnyDevice {
+    /// 32 byte Device Context.
+    Byte32(Device32Byte),
+    /// 64 byte Device Context.
+    Byte64(Device64Byte),
+}
+impl AnyDevice {
+    /// Creates an empty Device Context whose size is indicated by the Context Size bit of
+    /// `hccparams1`.
+    #[must_use]
+    pub fn new(hccparams1: CapabilityParameters1) -> Self {
+        if hccparams1.context_size() {
+            Self::Byte64(Device64Byte::new_64byte())
+        } else {
+            Self::Byte32(Device32Byte::new_32byte())
+        }
+    }
+
+    /// Reinterprets `buf` as a Device Context whose size is indicated by the Context Size bit of
+    /// `hccparams1`.
+    ///
+    /// This is useful to read the Output Device Context which the xHC updates.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if `buf` is not 4-byte aligned, or if the length of `buf` is
+    /// not the same as the size of the Device Context.
+    pub fn from_bytes(
+        buf: &[u8],
+        hccparams1: CapabilityParameters1,
+    ) -> Result<&dyn DeviceHandler, InvalidBuffer> {
+        if hccparams1.context_size() {
+            Ok(reinterpret::<Device64Byte>(buf)?)
+        } else {
+            Ok(reinterpret::<Device32Byte>(buf)?)
+        }
+    }
+
+    /// Reinterprets `buf` as a mutable Device Context whose size is indicated by the Context Size
+    /// bit of `hccparams1`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if `buf` is not 4-byte aligned, or if the length of `buf` is
+    /// not the same as the size of the Device Context.
+    pub fn from_bytes_mut(
+        buf: &mut [u8],
+        hccparams1: CapabilityParameters1,
+    ) -> Result<&mut dyn DeviceHandler, InvalidBuffer> {
+        if hccparams1.context_size() {
+            Ok(reinterpret_mut::<Device64Byte>(buf)?)
+        } else {
+            Ok(reinterpret_mut::<Device32Byte>(buf)?)
+        }
+    }
+}
+impl DeviceHandler for AnyDevice {
+    fn slot(&self) -> &dyn SlotHandler {
+        match self {
+            Self::Byte32(d) => d.slot(),
+            Self::Byte64(d) => d.slot(),
+        }
+    }
+
+    fn slot_mut(&mut self) -> &mut dyn SlotHandler {
+        match self {
+            Self::Byte32(d) => d.slot_mut(),
+            Self::Byte64(d) => d.slot_mut(),
+        }
+    }
+
+    fn endpoint(&self, dci: usize) -> &dyn EndpointHandler {
+        match self {
+            Self::Byte32(d) => d.endpoint(dci),
+            Self::Byte64(d) => d.endpoint(dci),
+        }
+    }
+
+    fn endpoint_mut(&mut self, dci: usize) -> &mut dyn EndpointHandler {
+        match self {
+            Self::Byte32(d) => d.endpoint_mut(dci),
+            Self::Byte64(d) => d.endpoint_mut(dci),
+        }
+    }
+}
+
+/// An error returned when a byte buffer cannot be reinterpreted as a Context.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum InvalidBuffer {
+    /// The buffer is not aligned correctly.
+    Misaligned,
+    /// The length of the buffer is not the same as the size of the Context.
+    WrongLength {
+        /// The size of the Context.
+        expected: usize,
+        /// The length of the buffer.
+        actual: usize,
+    },
+}
+
+/// A marker trait of the Contexts which consist only of `u32` values, so that any bit pattern is a
+/// valid value.
+trait Plain {}
+impl<const N: usize> Plain for Input<N> {}
+impl<const N: usize> Plain for Device<N> {}
+
+fn check_layout<T: Plain>(buf: &[u8]) -> Result<(), InvalidBuffer> {
+    if buf.len() != size_of::<T>() {
+        Err(InvalidBuffer::WrongLength {
+            expected: size_of::<T>(),
+            actual: buf.len(),
+        })
+    } else if buf.as_ptr().align_offset(align_of::<T>()) != 0 {
+        Err(InvalidBuffer::Misaligned)
+    } else {
+        Ok(())
+    }
+}
+
+fn reinterpret<T: Plain>(buf: &[u8]) -> Result<&T, InvalidBuffer> {
+    check_layout::<T>(buf)?;
+
+    // SAFETY: The size and the alignment are checked, and any bit pattern is valid for `T`.
+    Ok(unsafe { &*buf.as_ptr().cast::<T>() })
+}
+
+fn reinterpret_mut<T: Plain>(buf: &mut [u8]) -> Result<&mut T, InvalidBuffer> {
+    check_layout::<T>(buf)?;
+
+    // SAFETY: The size and the alignment are checked, and any bit pattern is valid for `T`.
+    Ok(unsafe { &mut *buf.as_mut_ptr().cast::<T>() })
+}
+
 /// Slot Context.
 ///
 /// Refer to [`SlotHandler`] for the available methods.
@@ -519,6 +738,569 @@ pub trait EndpointHandler: AsRef<[u32]> + AsMut<[u32]> {
     );
 }
 
+/// Stream Context.
+///
+/// Unlike the other Contexts, the size of a Stream Context is always 16 bytes regardless of the
+/// Context Size field of HCCPARAMS1, so there are no 32 and 64 byte variants.
+///
+/// Refer to [`StreamHandler`] for the available methods.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Stream([u32; 4]);
+impl Stream {
+    /// Creates an empty Stream Context.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self([0; 4])
+    }
+}
+impl AsRef<[u32]> for Stream {
+    fn as_ref(&self) -> &[u32] {
+        &self.0
+    }
+}
+impl AsMut<[u32]> for Stream {
+    fn as_mut(&mut self) -> &mut [u32] {
+        &mut self.0
+    }
+}
+impl StreamHandler for Stream {}
+impl_debug_from_methods! {
+    Stream {
+        dequeue_cycle_state,
+        stream_context_type,
+        tr_dequeue_pointer,
+        stopped_edtla,
+    }
+}
+
+/// A trait to handle Stream Context.
+pub trait StreamHandler: AsRef<[u32]> + AsMut<[u32]> {
+    rw_bit_cx!([0](0), dequeue_cycle_state, "Dequeue Cycle State");
+
+    /// Returns Stream Context Type.
+    #[must_use]
+    fn stream_context_type(&self) -> StreamContextType {
+        let v = self.as_ref()[0].get_bits(1..=3);
+        let t = FromPrimitive::from_u32(v);
+        t.expect("Invalid Stream Context Type.")
+    }
+
+    /// Sets Stream Context Type.
+    fn set_stream_context_type(&mut self, t: StreamContextType) {
+        self.as_mut()[0].set_bits(1..=3, t as _);
+    }
+
+    /// Returns the TR Dequeue Pointer.
+    #[must_use]
+    fn tr_dequeue_pointer(&self) -> u64 {
+        let l: u64 = self.as_ref()[0].into();
+        let u: u64 = self.as_ref()[1].into();
+
+        ((u << 32) | l) & !0b1111
+    }
+
+    /// Sets the TR Dequeue Pointer.
+    ///
+    /// If Stream Context Type is one of the Primary SSA values, the pointer points to a Secondary
+    /// Stream Context Array.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `a` is not 16-byte aligned.
+    fn set_tr_dequeue_pointer(&mut self, a: u64) {
+        assert_eq!(a % 16, 0, "TR Dequeue Pointer must be 16-byte aligned.");
+
+        let l: u32 = a.get_bits(0..32).try_into().unwrap();
+        let u: u32 = a.get_bits(32..64).try_into().unwrap();
+
+        self.as_mut()[0].set_bits(4..32, l.get_bits(4..32));
+        self.as_mut()[1] = u;
+    }
+
+    rw_field_cx!([2](0..=23), stopped_edtla, "Stopped EDTLA", u32);
+}
+
+/// Stream Context Array.
+///
+/// This struct is a builder of a Primary or Secondary Stream Context Array. The memory of the
+/// array must be accessible by the xHC.
+///
+/// # Examples
+///
+/// ```
+/// use xhci::context::{self, EndpointHandler, Stream, StreamContextArray, StreamContextType};
+/// use xhci::ring::{transfer, Segment};
+///
+/// let mut primary = [Stream::new(); 4];
+/// let mut secondary = [Stream::new(); 8];
+/// let mut trbs = [[0; 4]; 16];
+///
+/// let ring = transfer::Ring::<1>::new(Segment::new(&mut trbs, 0x3000));
+///
+/// let mut secondary = StreamContextArray::new_secondary(&mut secondary, 0x2000);
+/// secondary.set_transfer_ring(1, &ring);
+///
+/// let mut primary = StreamContextArray::new_primary(&mut primary, 0x1000);
+/// primary.set_secondary_array(1, &secondary);
+///
+/// let mut ep = context::Endpoint::new_32byte();
+/// primary.initialize_endpoint_context(&mut ep);
+///
+/// assert_eq!(ep.max_primary_streams(), 1);
+/// assert!(!ep.linear_stream_array());
+/// assert_eq!(primary.entry(1).stream_context_type(), StreamContextType::PrimarySsa8);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
+pub struct StreamContextArray<'a> {
+    contexts: &'a mut [Stream],
+    phys_base: u64,
+    primary: bool,
+    linear: bool,
+}
+impl<'a> StreamContextArray<'a> {
+    /// Creates a Primary Stream Context Array.
+    ///
+    /// `contexts` is the memory of the array, and `phys_base` is the physical address of
+    /// `contexts[0]`. All the Stream Contexts are cleared to 0. The entry 0 is reserved.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the length of `contexts` is not a power of 2 within `4..=65536`, or if
+    /// `phys_base` is not 64-byte aligned.
+    pub fn new_primary(contexts: &'a mut [Stream], phys_base: u64) -> Self {
+        assert!(
+            contexts.len().is_power_of_two() && (4..=65536).contains(&contexts.len()),
+            "The size of a Primary Stream Context Array must be a power of 2 within 4..=65536."
+        );
+
+        Self::new(contexts, phys_base, true)
+    }
+
+    /// Creates a Secondary Stream Context Array.
+    ///
+    /// `contexts` is the memory of the array, and `phys_base` is the physical address of
+    /// `contexts[0]`. All the Stream Contexts are cleared to 0.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the length of `contexts` is not a power of 2 within `8..=256`, or if
+    /// `phys_base` is not 64-byte aligned.
+    pub fn new_secondary(contexts: &'a mut [Stream], phys_base: u64) -> Self {
+        assert!(
+            contexts.len().is_power_of_two() && (8..=256).contains(&contexts.len()),
+            "The size of a Secondary Stream Context Array must be a power of 2 within 8..=256."
+        );
+
+        Self::new(contexts, phys_base, false)
+    }
+
+    /// Returns the physical address of the first entry.
+    #[must_use]
+    pub fn phys_base(&self) -> u64 {
+        self.phys_base
+    }
+
+    /// Returns the number of entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    /// Returns `false`. A Stream Context Array is never empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    /// Makes the entry `i` point to `ring`.
+    ///
+    /// The Stream Context Type is set to Primary Transfer Ring for a Primary Stream Context
+    /// Array, and to Secondary Transfer Ring for a Secondary one.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i` is out of range, or if `i == 0` for a Primary Stream Context
+    /// Array.
+    pub fn set_transfer_ring<const N: usize>(&mut self, i: usize, ring: &Ring<'_, N>) {
+        let t = if self.primary {
+            StreamContextType::PrimaryTransferRing
+        } else {
+            StreamContextType::SecondaryTransferRing
+        };
+
+        let s = self.entry_mut(i);
+        ring.initialize_stream_context(s);
+        s.set_stream_context_type(t);
+    }
+
+    /// Makes the entry `i` point to a Secondary Stream Context Array.
+    ///
+    /// An Endpoint Context initialized by this array no longer uses the Linear Stream Array.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this array is a Secondary Stream Context Array, if `secondary` is
+    /// not a Secondary one, if this array has more than 256 entries, if `i` is out of range, or if
+    /// `i == 0`.
+    pub fn set_secondary_array(&mut self, i: usize, secondary: &StreamContextArray<'_>) {
+        assert!(
+            self.primary && !secondary.primary,
+            "Only a Primary Stream Context Array can point to a Secondary one."
+        );
+        // Max Primary Streams must be within 1..=7 if the Linear Stream Array is not used.
+        assert!(
+            self.len() <= 256,
+            "A Primary Stream Context Array pointing to Secondary ones must not have more than 256 entries."
+        );
+
+        let t = match secondary.len() {
+            8 => StreamContextType::PrimarySsa8,
+            16 => StreamContextType::PrimarySsa16,
+            32 => StreamContextType::PrimarySsa32,
+            64 => StreamContextType::PrimarySsa64,
+            128 => StreamContextType::PrimarySsa128,
+            256 => StreamContextType::PrimarySsa256,
+            _ => unreachable!("The size of a Secondary Stream Context Array is checked."),
+        };
+
+        let s = self.entry_mut(i);
+        *s = Stream::new();
+        s.set_tr_dequeue_pointer(secondary.phys_base);
+        s.set_stream_context_type(t);
+
+        self.linear = false;
+    }
+
+    /// Returns a handler of the entry `i`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i` is out of range.
+    #[must_use]
+    pub fn entry(&self, i: usize) -> &dyn StreamHandler {
+        &self.contexts[i]
+    }
+
+    /// Sets the TR Dequeue Pointer, Max Primary Streams, and the Linear Stream Array fields of
+    /// `ep` so that `ep` uses this array.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if this array is a Secondary Stream Context Array.
+    pub fn initialize_endpoint_context(&self, ep: &mut dyn EndpointHandler) {
+        assert!(
+            self.primary,
+            "An Endpoint Context must point to a Primary Stream Context Array."
+        );
+
+        let max_primary_streams = self.contexts.len().trailing_zeros() - 1;
+
+        ep.set_tr_dequeue_pointer(self.phys_base);
+        ep.set_max_primary_streams(max_primary_streams.try_into().unwrap());
+        if self.linear {
+            ep.set_linear_stream_array();
+        } else {
+            ep.clear_linear_stream_array();
+        }
+    }
+
+    fn new(contexts: &'a mut [Stream], phys_base: u64, primary: bool) -> Self {
+        assert!(
+            phys_base.trailing_zeros() >= 6,
+            "The base address of a Stream Context Array must be 64-byte aligned."
+        );
+
+        contexts.fill(Stream::new());
+
+        Self {
+            contexts,
+            phys_base,
+            primary,
+            linear: true,
+        }
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut Stream {
+        assert!(
+            !(self.primary && i == 0),
+            "The entry 0 of a Primary Stream Context Array is reserved."
+        );
+
+        &mut self.contexts[i]
+    }
+}
+
+/// The memory of a Device Context Base Address Array.
+///
+/// The memory is 4096-byte aligned so that it never crosses a page boundary, and its layout is
+/// exactly that of the array read by the xHC. Access the entries through
+/// [`DeviceContextBaseAddressArray`].
+#[repr(C, align(4096))]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct DeviceContextBaseAddressArrayMemory([u64; 256]);
+impl DeviceContextBaseAddressArrayMemory {
+    /// Creates a zeroed memory of a Device Context Base Address Array.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self([0; 256])
+    }
+}
+impl Default for DeviceContextBaseAddressArrayMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Device Context Base Address Array.
+///
+/// The entry 0 holds the address of the Scratchpad Buffer Array, and the entry `n` holds the
+/// address of the Device Context of the Device Slot `n`.
+///
+/// The entries are written with volatile writes as the xHC reads them.
+pub struct DeviceContextBaseAddressArray<'a> {
+    memory: &'a mut DeviceContextBaseAddressArrayMemory,
+    number_of_device_slots: u8,
+}
+impl<'a> DeviceContextBaseAddressArray<'a> {
+    /// Creates a Device Context Base Address Array.
+    ///
+    /// All the entries of `memory` are cleared to 0. The valid Slot IDs are bounded by the Number
+    /// of Device Slots field of `hcsparams1`.
+    pub fn new(
+        memory: &'a mut DeviceContextBaseAddressArrayMemory,
+        hcsparams1: StructuralParameters1,
+    ) -> Self {
+        let mut a = Self {
+            memory,
+            number_of_device_slots: hcsparams1.number_of_device_slots(),
+        };
+
+        for i in 0..a.memory.0.len() {
+            a.write(i, 0);
+        }
+
+        a
+    }
+
+    /// Returns the address of the Device Context of the Device Slot `slot_id`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot_id` is 0 or greater than the Number of Device Slots.
+    #[must_use]
+    pub fn device_context_address(&self, slot_id: u8) -> u64 {
+        self.assert_slot_id(slot_id);
+
+        self.read(slot_id.into())
+    }
+
+    /// Sets the address of the Device Context of the Device Slot `slot_id`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot_id` is 0 or greater than the Number of Device Slots, or if `a`
+    /// is not 64-byte aligned.
+    pub fn set_device_context_address(&mut self, slot_id: u8, a: u64) {
+        self.assert_slot_id(slot_id);
+        assert!(
+            a.trailing_zeros() >= 6,
+            "The address of a Device Context must be 64-byte aligned."
+        );
+
+        self.write(slot_id.into(), a);
+    }
+
+    /// Clears the address of the Device Context of the Device Slot `slot_id`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot_id` is 0 or greater than the Number of Device Slots.
+    pub fn clear_device_context_address(&mut self, slot_id: u8) {
+        self.assert_slot_id(slot_id);
+
+        self.write(slot_id.into(), 0);
+    }
+
+    /// Returns the address of the Scratchpad Buffer Array.
+    #[must_use]
+    pub fn scratchpad_buffer_array_address(&self) -> u64 {
+        self.read(0)
+    }
+
+    /// Sets the address of the Scratchpad Buffer Array.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `a` is not 64-byte aligned.
+    pub fn set_scratchpad_buffer_array_address(&mut self, a: u64) {
+        assert!(
+            a.trailing_zeros() >= 6,
+            "The address of a Scratchpad Buffer Array must be 64-byte aligned."
+        );
+
+        self.write(0, a);
+    }
+
+    fn read(&self, i: usize) -> u64 {
+        // SAFETY: `self.memory.0[i]` is a valid reference.
+        unsafe { ptr::read_volatile(ptr::addr_of!(self.memory.0[i])) }
+    }
+
+    fn write(&mut self, i: usize, v: u64) {
+        // SAFETY: `self.memory.0[i]` is a valid reference.
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!(self.memory.0[i]), v) };
+    }
+
+    fn assert_slot_id(&self, slot_id: u8) {
+        assert_ne!(slot_id, 0, "Slot ID 0 is reserved.");
+        assert!(
+            slot_id <= self.number_of_device_slots,
+            "Slot ID must not be greater than the Number of Device Slots."
+        );
+    }
+}
+impl fmt::Debug for DeviceContextBaseAddressArray<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceContextBaseAddressArray")
+            .field(
+                "scratchpad_buffer_array_address",
+                &self.scratchpad_buffer_array_address(),
+            )
+            .field(
+                "device_context_addresses",
+                &&self.memory.0[1..=usize::from(self.number_of_device_slots)],
+            )
+            .finish()
+    }
+}
+
+/// Scratchpad Buffer Array.
+///
+/// `N` is the capacity of the array, which must not be less than the number of the Scratchpad
+/// Buffers the xHC requires. Refer to [`ScratchpadBufferRequirement`] for the number.
+///
+/// The entries are written with volatile writes as the xHC reads them.
+#[repr(C, align(64))]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct ScratchpadBufferArray<const N: usize>([u64; N]);
+impl<const N: usize> ScratchpadBufferArray<N> {
+    /// Creates an empty Scratchpad Buffer Array.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self([0; N])
+    }
+
+    /// Sets the addresses of all the Scratchpad Buffers the xHC requires.
+    ///
+    /// `phys_base` is the physical address of a contiguous memory of
+    /// [`ScratchpadBufferRequirement::total_bytes`] bytes, which is divided into the buffers. The
+    /// remaining entries are cleared to 0.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `N` is less than the number of the Scratchpad Buffers, or if
+    /// `phys_base` is not aligned to the page size.
+    pub fn initialize(&mut self, requirement: ScratchpadBufferRequirement, phys_base: u64) {
+        let ScratchpadBufferRequirement { count, page_size } = requirement;
+        assert!(
+            count <= N,
+            "The Scratchpad Buffer Array has less entries than the xHC requires."
+        );
+
+        let page_size = u64::try_from(page_size).unwrap();
+        assert_eq!(
+            phys_base % page_size,
+            0,
+            "The Scratchpad Buffers must be page-aligned."
+        );
+
+        let mut a = phys_base;
+        for i in 0..N {
+            self.write(i, if i < count { a } else { 0 });
+            a += page_size;
+        }
+    }
+
+    /// Returns the address of the Scratchpad Buffer `i`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i >= N`.
+    #[must_use]
+    pub fn get(&self, i: usize) -> u64 {
+        // SAFETY: `self.0[i]` is a valid reference.
+        unsafe { ptr::read_volatile(ptr::addr_of!(self.0[i])) }
+    }
+
+    /// Sets the address of the Scratchpad Buffer `i`.
+    ///
+    /// The buffer must be aligned to the page size reported by the Page Size Register. This
+    /// method checks only the 4096-byte alignment, which is that of the smallest page size. Use
+    /// [`ScratchpadBufferArray::initialize`] to check the alignment against the actual page size.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i >= N`, or if `a` is not 4096-byte aligned.
+    pub fn set(&mut self, i: usize, a: u64) {
+        assert!(
+            a.trailing_zeros() >= 12,
+            "The address of a Scratchpad Buffer must be page-aligned."
+        );
+
+        self.write(i, a);
+    }
+
+    fn write(&mut self, i: usize, a: u64) {
+        // SAFETY: `self.0[i]` is a valid reference.
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!(self.0[i]), a) };
+    }
+}
+impl<const N: usize> fmt::Debug for ScratchpadBufferArray<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries((0..N).map(|i| self.get(i))).finish()
+    }
+}
+impl<const N: usize> Default for ScratchpadBufferArray<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The number and the size of the Scratchpad Buffers the xHC requires.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct ScratchpadBufferRequirement {
+    /// The number of the Scratchpad Buffers.
+    pub count: usize,
+    /// The size of each Scratchpad Buffer in bytes, which is also its alignment.
+    pub page_size: usize,
+}
+impl ScratchpadBufferRequirement {
+    /// Calculates the requirement from the Max Scratchpad Buffers field of `hcsparams2` and the
+    /// Page Size Register.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `pagesize` indicates no page size.
+    #[must_use]
+    pub fn new(hcsparams2: StructuralParameters2, pagesize: PageSizeRegister) -> Self {
+        let p = pagesize.get();
+        assert_ne!(p, 0, "The Page Size Register indicates no page size.");
+
+        Self {
+            count: hcsparams2.max_scratchpad_buffers().try_into().unwrap(),
+            page_size: 1 << (p.trailing_zeros() + 12),
+        }
+    }
+
+    /// Returns the total size of the Scratchpad Buffers in bytes.
+    #[must_use]
+    pub fn total_bytes(self) -> usize {
+        self.count * self.page_size
+    }
+}
+
 /// Slot State.
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
 pub enum SlotState {
@@ -570,3 +1352,342 @@ pub enum EndpointType {
     /// Interrupt In.
     InterruptIn = 7,
 }
+
+/// Stream Context Type.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum StreamContextType {
+    /// Secondary Transfer Ring. Used only in a Secondary Stream Context Array.
+    SecondaryTransferRing = 0,
+    /// Primary Transfer Ring.
+    PrimaryTransferRing = 1,
+    /// Primary SSA, 8 entries.
+    PrimarySsa8 = 2,
+    /// Primary SSA, 16 entries.
+    PrimarySsa16 = 3,
+    /// Primary SSA, 32 entries.
+    PrimarySsa32 = 4,
+    /// Primary SSA, 64 entries.
+    PrimarySsa64 = 5,
+    /// Primary SSA, 128 entries.
+    PrimarySsa128 = 6,
+    /// Primary SSA, 256 entries.
+    PrimarySsa256 = 7,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{Mmio, NUM_SLOTS};
+    use crate::ring::Segment;
+    use crate::Registers;
+    use alloc::vec;
+
+    #[repr(C, align(64))]
+    struct Aligned([u8; 2 * size_of::<Input64Byte>()]);
+
+    fn registers(mmio: &Mmio) -> Registers<crate::mock::Mapper> {
+        // SAFETY: `mmio` has the Capability Registers at offset 0.
+        unsafe { Registers::new(mmio.base(), mmio.mapper()) }
+    }
+
+    fn hccparams1(context_size: bool) -> CapabilityParameters1 {
+        let mmio = Mmio::new();
+        if context_size {
+            mmio.write_u32(0x10, mmio.read_u32(0x10) | 1 << 2);
+        }
+
+        registers(&mmio).capability.hccparams1.read_volatile()
+    }
+
+    #[test]
+    fn from_bytes_checks_layout() {
+        let mut buf = Aligned([0; 2 * size_of::<Input64Byte>()]);
+
+        assert_eq!(
+            AnyInput::from_bytes_mut(&mut buf.0[..1055], hccparams1(false)).err(),
+            Some(InvalidBuffer::WrongLength {
+                expected: 1056,
+                actual: 1055
+            })
+        );
+        assert_eq!(
+            AnyDevice::from_bytes(&buf.0[..2112], hccparams1(true)).err(),
+            Some(InvalidBuffer::WrongLength {
+                expected: 2048,
+                actual: 2112
+            })
+        );
+
+        assert_eq!(
+            AnyInput::from_bytes_mut(&mut buf.0[1..2113], hccparams1(true)).err(),
+            Some(InvalidBuffer::Misaligned)
+        );
+        assert_eq!(
+            AnyDevice::from_bytes(&buf.0[2..1026], hccparams1(false)).err(),
+            Some(InvalidBuffer::Misaligned)
+        );
+        assert_eq!(
+            AnyDevice::from_bytes_mut(&mut buf.0[4..2052], hccparams1(true)).map(|_| ()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn writes_through_reinterpreted_contexts() {
+        for (context_size, bytes) in [(false, 32), (true, 64)] {
+            let mut buf = Aligned([0; 2 * size_of::<Input64Byte>()]);
+            let dword = |buf: &Aligned, offset: usize| {
+                u32::from_le_bytes(buf.0[offset..offset + 4].try_into().unwrap())
+            };
+
+            let len = 33 * bytes;
+            let input =
+                AnyInput::from_bytes_mut(&mut buf.0[..len], hccparams1(context_size)).unwrap();
+            input.control_mut().set_add_context_flag(1);
+            input.device_mut().slot_mut().set_context_entries(1);
+            input.device_mut().endpoint_mut(1).set_max_packet_size(512);
+
+            assert_eq!(dword(&buf, 4), 1 << 1);
+            assert_eq!(dword(&buf, bytes), 1 << 27);
+            assert_eq!(dword(&buf, 2 * bytes + 4), 512 << 16);
+
+            // The Device Context follows the Input Control Context.
+            let output = &buf.0[bytes..len];
+            let device = AnyDevice::from_bytes(output, hccparams1(context_size)).unwrap();
+            assert_eq!(device.slot().context_entries(), 1);
+            assert_eq!(device.endpoint(1).max_packet_size(), 512);
+        }
+    }
+
+    #[test]
+    fn device_context_base_address_array() {
+        assert_eq!(size_of::<DeviceContextBaseAddressArrayMemory>(), 4096);
+        assert_eq!(align_of::<DeviceContextBaseAddressArrayMemory>(), 4096);
+
+        let hcsparams1 = registers(&Mmio::new())
+            .capability
+            .hcsparams1
+            .read_volatile();
+        let mut memory = DeviceContextBaseAddressArrayMemory::new();
+        memory.0[usize::from(NUM_SLOTS)] = 0xdead_0000;
+
+        let mut a = DeviceContextBaseAddressArray::new(&mut memory, hcsparams1);
+        assert_eq!(a.device_context_address(NUM_SLOTS), 0);
+
+        a.set_device_context_address(NUM_SLOTS, 0x1040);
+        a.set_scratchpad_buffer_array_address(0x2000);
+        assert_eq!(a.device_context_address(NUM_SLOTS), 0x1040);
+
+        assert_eq!(memory.0[0], 0x2000);
+        assert_eq!(memory.0[usize::from(NUM_SLOTS)], 0x1040);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slot ID 0 is reserved.")]
+    fn device_context_address_of_slot_0() {
+        let hcsparams1 = registers(&Mmio::new())
+            .capability
+            .hcsparams1
+            .read_volatile();
+        let mut memory = DeviceContextBaseAddressArrayMemory::new();
+
+        DeviceContextBaseAddressArray::new(&mut memory, hcsparams1)
+            .set_device_context_address(0, 0x1040);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slot ID must not be greater than the Number of Device Slots.")]
+    fn device_context_address_beyond_max_slots() {
+        let hcsparams1 = registers(&Mmio::new())
+            .capability
+            .hcsparams1
+            .read_volatile();
+        let mut memory = DeviceContextBaseAddressArrayMemory::new();
+
+        DeviceContextBaseAddressArray::new(&mut memory, hcsparams1)
+            .set_device_context_address(NUM_SLOTS + 1, 0x1040);
+    }
+
+    fn scratchpad_buffer_requirement() -> ScratchpadBufferRequirement {
+        let mmio = Mmio::new();
+
+        // Max Scratchpad Buffers Hi = 1, and Lo = 3.
+        mmio.write_u32(0x08, 3 << 27 | 1 << 21);
+        // 16KB pages.
+        mmio.write_u32(0x28, 1 << 2);
+
+        let r = registers(&mmio);
+        ScratchpadBufferRequirement::new(
+            r.capability.hcsparams2.read_volatile(),
+            r.operational.pagesize.read_volatile(),
+        )
+    }
+
+    #[test]
+    fn scratchpad_buffers() {
+        let requirement = scratchpad_buffer_requirement();
+        assert_eq!(requirement.count, 35);
+        assert_eq!(requirement.page_size, 0x4000);
+        assert_eq!(requirement.total_bytes(), 35 * 0x4000);
+
+        let mut array = ScratchpadBufferArray::<36>::new();
+        array.set(35, 0x1000);
+        array.initialize(requirement, 0x10_0000);
+        assert_eq!(array.get(0), 0x10_0000);
+        assert_eq!(array.get(34), 0x10_0000 + 34 * 0x4000);
+        assert_eq!(array.get(35), 0);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The Scratchpad Buffer Array has less entries than the xHC requires."
+    )]
+    fn too_small_scratchpad_buffer_array() {
+        ScratchpadBufferArray::<34>::new().initialize(scratchpad_buffer_requirement(), 0x10_0000);
+    }
+
+    #[test]
+    #[should_panic(expected = "The Scratchpad Buffers must be page-aligned.")]
+    fn misaligned_scratchpad_buffers() {
+        ScratchpadBufferArray::<35>::new().initialize(scratchpad_buffer_requirement(), 0x10_1000);
+    }
+
+    #[test]
+    fn stream_context_fields() {
+        let mut s = Stream::new();
+        s.set_dequeue_cycle_state();
+        s.set_stream_context_type(StreamContextType::PrimarySsa256);
+        s.set_tr_dequeue_pointer(0x1234_5678_9abc_def0);
+        s.set_stopped_edtla(0xab_cdef);
+
+        assert_eq!(s.0, [0x9abc_def0 | 7 << 1 | 1, 0x1234_5678, 0xab_cdef, 0]);
+        assert!(s.dequeue_cycle_state());
+        assert_eq!(s.stream_context_type(), StreamContextType::PrimarySsa256);
+        assert_eq!(s.tr_dequeue_pointer(), 0x1234_5678_9abc_def0);
+        assert_eq!(s.stopped_edtla(), 0xab_cdef);
+    }
+
+    #[test]
+    #[should_panic(expected = "TR Dequeue Pointer must be 16-byte aligned.")]
+    fn misaligned_stream_tr_dequeue_pointer() {
+        Stream::new().set_tr_dequeue_pointer(0x1008);
+    }
+
+    #[test]
+    fn stream_context_array_sizes() {
+        for len in [4, 65536] {
+            let mut contexts = vec![Stream::new(); len];
+            assert_eq!(
+                StreamContextArray::new_primary(&mut contexts, 0x1000).len(),
+                len
+            );
+        }
+        for len in [8, 256] {
+            let mut contexts = vec![Stream::new(); len];
+            assert_eq!(
+                StreamContextArray::new_secondary(&mut contexts, 0x1000).len(),
+                len
+            );
+        }
+
+        let mut ep = Endpoint32Byte::new_32byte();
+        let mut contexts = vec![Stream::new(); 65536];
+        StreamContextArray::new_primary(&mut contexts, 0x1000).initialize_endpoint_context(&mut ep);
+        assert_eq!(ep.max_primary_streams(), 15);
+        assert!(ep.linear_stream_array());
+        assert_eq!(ep.tr_dequeue_pointer(), 0x1000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The size of a Primary Stream Context Array must be a power of 2 within 4..=65536."
+    )]
+    fn too_small_primary_stream_context_array() {
+        let mut contexts = [Stream::new(); 2];
+        let _ = StreamContextArray::new_primary(&mut contexts, 0x1000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The size of a Primary Stream Context Array must be a power of 2 within 4..=65536."
+    )]
+    fn primary_stream_context_array_of_non_power_of_2() {
+        let mut contexts = [Stream::new(); 12];
+        let _ = StreamContextArray::new_primary(&mut contexts, 0x1000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The size of a Primary Stream Context Array must be a power of 2 within 4..=65536."
+    )]
+    fn too_large_primary_stream_context_array() {
+        let mut contexts = vec![Stream::new(); 131_072];
+        let _ = StreamContextArray::new_primary(&mut contexts, 0x1000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The size of a Secondary Stream Context Array must be a power of 2 within 8..=256."
+    )]
+    fn too_small_secondary_stream_context_array() {
+        let mut contexts = [Stream::new(); 4];
+        let _ = StreamContextArray::new_secondary(&mut contexts, 0x1000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The size of a Secondary Stream Context Array must be a power of 2 within 8..=256."
+    )]
+    fn too_large_secondary_stream_context_array() {
+        let mut contexts = [Stream::new(); 512];
+        let _ = StreamContextArray::new_secondary(&mut contexts, 0x1000);
+    }
+
+    #[test]
+    fn set_transfer_rings() {
+        let mut trbs = [[0; 4]; 16];
+        let ring = Ring::<1>::new(Segment::new(&mut trbs, 0x3000));
+
+        let mut contexts = [Stream::new(); 8];
+        let mut secondary = StreamContextArray::new_secondary(&mut contexts, 0x2000);
+        secondary.set_transfer_ring(0, &ring);
+        assert_eq!(
+            secondary.entry(0).stream_context_type(),
+            StreamContextType::SecondaryTransferRing
+        );
+        assert_eq!(secondary.entry(0).tr_dequeue_pointer(), 0x3000);
+
+        let mut contexts = [Stream::new(); 4];
+        let mut primary = StreamContextArray::new_primary(&mut contexts, 0x1000);
+        primary.set_transfer_ring(1, &ring);
+        assert_eq!(primary.entry(0).tr_dequeue_pointer(), 0);
+        assert_eq!(
+            primary.entry(1).stream_context_type(),
+            StreamContextType::PrimaryTransferRing
+        );
+        assert!(primary.entry(1).dequeue_cycle_state());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "A Primary Stream Context Array pointing to Secondary ones must not have more than 256 entries."
+    )]
+    fn secondary_array_in_large_primary_array() {
+        let mut secondary = [Stream::new(); 8];
+        let secondary = StreamContextArray::new_secondary(&mut secondary, 0x2000);
+
+        let mut primary = [Stream::new(); 512];
+        let mut primary = StreamContextArray::new_primary(&mut primary, 0x1000);
+        primary.set_secondary_array(1, &secondary);
+    }
+
+    #[test]
+    #[should_panic(expected = "The entry 0 of a Primary Stream Context Array is reserved.")]
+    fn transfer_ring_in_primary_stream_0() {
+        let mut trbs = [[0; 4]; 16];
+        let ring = Ring::<1>::new(Segment::new(&mut trbs, 0x3000));
+
+        let mut contexts = [Stream::new(); 4];
+        StreamContextArray::new_primary(&mut contexts, 0x1000).set_transfer_ring(0, &ring);
+    }
+}
@@ -0,0 +1,431 @@
+//! Host controller initialization.
+//!
+//! [`Controller`] performs the initialization sequence described in section 4.2 of the xHCI
+//! specification. Every wait has a timeout measured with a caller-supplied [`Clock`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use core::time::Duration;
+//! use xhci::controller::{Config, Controller};
+//! # use core::num::NonZeroUsize;
+//! # use xhci::accessor::Mapper;
+//! #
+//! # #[derive(Clone)]
+//! # struct MemoryMapper;
+//! # impl Mapper for MemoryMapper {
+//! #     unsafe fn map(&mut self, phys_base: usize, bytes: usize) -> NonZeroUsize {
+//! #         unimplemented!()
+//! #     }
+//! #
+//! #     fn unmap(&mut self, virt_base: usize, bytes: usize) {
+//! #         unimplemented!()
+//! #     }
+//! # }
+//! # fn timer_now() -> Duration {
+//! #     unimplemented!()
+//! # }
+//! #
+//! # let (mmio_base, mapper) = (0x1000, MemoryMapper);
+//! # let (dcbaa, command_ring, erst, event_ring) = (0x1_0000, 0x2_0000, 0x3_0000, 0x4_0000);
+//!
+//! let r = unsafe { xhci::Registers::new(mmio_base, mapper) };
+//! let mut xhc = Controller::new(r);
+//!
+//! let mut config = Config::default();
+//! config
+//!     .set_max_device_slots_enabled(8)
+//!     .set_device_context_base_address_array_pointer(dcbaa)
+//!     .set_command_ring_pointer(command_ring)
+//!     .set_event_ring_segment_table(erst, 1)
+//!     .set_event_ring_dequeue_pointer(event_ring);
+//!
+//! xhc.initialize(&config, &mut timer_now)
+//!     .expect("Failed to initialize the xHC.");
+//! ```
+
+use crate::registers::operational::{Operational, UsbStatusRegister};
+use crate::Registers;
+use accessor::Mapper;
+use core::time::Duration;
+
+/// The timeout [`Controller::new`] sets.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A source of the current time.
+///
+/// This trait is implemented for closures returning [`Duration`].
+pub trait Clock {
+    /// Returns the time elapsed since an arbitrary fixed point.
+    ///
+    /// The returned value must not decrease.
+    fn now(&mut self) -> Duration;
+}
+impl<F> Clock for F
+where
+    F: FnMut() -> Duration,
+{
+    fn now(&mut self) -> Duration {
+        self()
+    }
+}
+
+/// The values written to the registers during the initialization.
+///
+/// Each value is written to the register of the same name. The Ring Cycle State of the Command
+/// Ring Control Register is set to 1, and the Event Ring is the one of the Interrupter 0.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Config {
+    max_device_slots_enabled: u8,
+    device_context_base_address_array_pointer: u64,
+    command_ring_pointer: u64,
+    event_ring_segment_table_base_address: u64,
+    event_ring_segment_table_size: u16,
+    event_ring_dequeue_pointer: u64,
+}
+impl Config {
+    /// Sets the value of the Max Device Slots Enabled field.
+    pub fn set_max_device_slots_enabled(&mut self, n: u8) -> &mut Self {
+        self.max_device_slots_enabled = n;
+        self
+    }
+
+    /// Sets the value of the Device Context Base Address Array Pointer.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `p` is not 64-byte aligned.
+    pub fn set_device_context_base_address_array_pointer(&mut self, p: u64) -> &mut Self {
+        assert!(
+            p.trailing_zeros() >= 6,
+            "The Device Context Base Address Array Pointer must be 64-byte aligned."
+        );
+
+        self.device_context_base_address_array_pointer = p;
+        self
+    }
+
+    /// Sets the value of the Command Ring Pointer.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `p` is not 64-byte aligned.
+    pub fn set_command_ring_pointer(&mut self, p: u64) -> &mut Self {
+        assert!(
+            p.trailing_zeros() >= 6,
+            "The Command Ring Pointer must be 64-byte aligned."
+        );
+
+        self.command_ring_pointer = p;
+        self
+    }
+
+    /// Sets the base address and the number of the entries of the Event Ring Segment Table.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `base` is not 64-byte aligned, or if `size` is 0.
+    pub fn set_event_ring_segment_table(&mut self, base: u64, size: u16) -> &mut Self {
+        assert!(
+            base.trailing_zeros() >= 6,
+            "The Event Ring Segment Table Base Address must be 64-byte aligned."
+        );
+        assert_ne!(
+            size, 0,
+            "The Event Ring Segment Table must contain at least one entry."
+        );
+
+        self.event_ring_segment_table_base_address = base;
+        self.event_ring_segment_table_size = size;
+        self
+    }
+
+    /// Sets the value of the Event Ring Dequeue Pointer.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `p` is not 16-byte aligned.
+    pub fn set_event_ring_dequeue_pointer(&mut self, p: u64) -> &mut Self {
+        assert!(
+            p.trailing_zeros() >= 4,
+            "The Event Ring Dequeue Pointer must be 16-byte aligned."
+        );
+
+        self.event_ring_dequeue_pointer = p;
+        self
+    }
+}
+
+/// An xHC which is initialized through [`Registers`].
+#[derive(Debug)]
+pub struct Controller<M>
+where
+    M: Mapper + Clone,
+{
+    registers: Registers<M>,
+    timeout: Duration,
+}
+impl<M> Controller<M>
+where
+    M: Mapper + Clone,
+{
+    /// Creates a new instance. The timeout of each wait is [`DEFAULT_TIMEOUT`].
+    #[must_use]
+    pub fn new(registers: Registers<M>) -> Self {
+        Self {
+            registers,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Returns the timeout of each wait.
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout of each wait.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns a reference to the registers.
+    #[must_use]
+    pub fn registers(&self) -> &Registers<M> {
+        &self.registers
+    }
+
+    /// Returns a mutable reference to the registers.
+    pub fn registers_mut(&mut self) -> &mut Registers<M> {
+        &mut self.registers
+    }
+
+    /// Returns the registers, consuming `self`.
+    #[must_use]
+    pub fn into_registers(self) -> Registers<M> {
+        self.registers
+    }
+
+    /// Halts and resets the xHC.
+    ///
+    /// This method waits until the Controller Not Ready bit is cleared, clears the Run/Stop bit
+    /// and waits until the xHC halts, and then resets the xHC.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if a wait times out, or if the Host Controller Error bit is
+    /// set.
+    pub fn reset(&mut self, clock: &mut dyn Clock) -> Result<(), Error> {
+        let mut timer = Timer::new(clock, self.timeout);
+        let o = &mut self.registers.operational;
+
+        timer.wait(Error::ControllerNotReady, || {
+            status(o).map(|s| !s.controller_not_ready())
+        })?;
+
+        if !status(o)?.hc_halted() {
+            o.usbcmd.update_volatile(|u| {
+                u.clear_run_stop();
+            });
+            timer.wait(Error::NotHalted, || {
+                status(o).map(UsbStatusRegister::hc_halted)
+            })?;
+        }
+
+        o.usbcmd.update_volatile(|u| {
+            u.set_host_controller_reset();
+        });
+        timer.wait(Error::ResetNotCompleted, || {
+            let reset = o.usbcmd.read_volatile().host_controller_reset();
+            status(o).map(|s| !reset && !s.controller_not_ready())
+        })
+    }
+
+    /// Resets the xHC, writes the values of `config` to the registers, enables the interrupts of
+    /// the Interrupter 0, and starts the xHC.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if a wait times out, or if the Host Controller Error bit is
+    /// set.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the Max Device Slots Enabled field of `config` is 0 or greater than
+    /// the number of the Device Slots the xHC supports, or if the Event Ring Segment Table is not
+    /// set.
+    pub fn initialize(&mut self, config: &Config, clock: &mut dyn Clock) -> Result<(), Error> {
+        let max_slots = self
+            .registers
+            .capability
+            .hcsparams1
+            .read_volatile()
+            .number_of_device_slots();
+        assert!(
+            (1..=max_slots).contains(&config.max_device_slots_enabled),
+            "The Max Device Slots Enabled must be within 1..=MaxSlots."
+        );
+        assert_ne!(
+            config.event_ring_segment_table_size, 0,
+            "The Event Ring Segment Table must be set."
+        );
+
+        self.reset(clock)?;
+
+        let o = &mut self.registers.operational;
+        o.config.update_volatile(|c| {
+            c.set_max_device_slots_enabled(config.max_device_slots_enabled);
+        });
+        o.dcbaap.update_volatile(|d| {
+            d.set(config.device_context_base_address_array_pointer);
+        });
+        o.crcr.update_volatile(|c| {
+            c.set_command_ring_pointer(config.command_ring_pointer);
+            c.set_ring_cycle_state();
+        });
+
+        let mut i = self.registers.interrupter_register_set.interrupter_mut(0);
+        i.erstsz.update_volatile(|s| {
+            s.set(config.event_ring_segment_table_size);
+        });
+        i.erdp.update_volatile(|e| {
+            e.set_event_ring_dequeue_pointer(config.event_ring_dequeue_pointer);
+        });
+        i.erstba.update_volatile(|b| {
+            b.set(config.event_ring_segment_table_base_address);
+        });
+        i.iman.update_volatile(|m| {
+            m.set_0_interrupt_pending().set_interrupt_enable();
+        });
+
+        let o = &mut self.registers.operational;
+        o.usbcmd.update_volatile(|u| {
+            u.set_interrupter_enable().set_run_stop();
+        });
+
+        let mut timer = Timer::new(clock, self.timeout);
+        timer.wait(Error::NotRunning, || status(o).map(|s| !s.hc_halted()))
+    }
+}
+
+/// Errors returned while initializing an xHC.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum Error {
+    /// The Controller Not Ready bit was not cleared in time.
+    ControllerNotReady,
+    /// The `HCHalted` bit was not set in time after clearing the Run/Stop bit.
+    NotHalted,
+    /// The Host Controller Reset bit was not cleared in time.
+    ResetNotCompleted,
+    /// The `HCHalted` bit was not cleared in time after setting the Run/Stop bit.
+    NotRunning,
+    /// The Host Controller Error bit is set.
+    HostControllerError,
+}
+
+struct Timer<'a> {
+    clock: &'a mut dyn Clock,
+    timeout: Duration,
+}
+impl<'a> Timer<'a> {
+    fn new(clock: &'a mut dyn Clock, timeout: Duration) -> Self {
+        Self { clock, timeout }
+    }
+
+    fn wait(&mut self, e: Error, mut f: impl FnMut() -> Result<bool, Error>) -> Result<(), Error> {
+        let start = self.clock.now();
+
+        loop {
+            if f()? {
+                return Ok(());
+            }
+
+            if self.clock.now().saturating_sub(start) >= self.timeout {
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn status<M>(o: &Operational<M>) -> Result<UsbStatusRegister, Error>
+where
+    M: Mapper + Clone,
+{
+    let s = o.usbsts.read_volatile();
+
+    if s.host_controller_error() {
+        Err(Error::HostControllerError)
+    } else {
+        Ok(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::controller::Controller as Model;
+    use crate::mock::{Mmio, OPERATIONAL_OFFSET};
+
+    #[test]
+    fn initialize_with_model() {
+        let mmio = Mmio::new();
+        let memory = Mmio::zeroed(0x1000);
+        let base = u64::try_from(memory.base()).unwrap();
+
+        // SAFETY: `memory` is identity-mapped.
+        let mut model = unsafe { Model::new(mmio.clone()) };
+        let mut xhc = Controller::new(unsafe { Registers::new(mmio.base(), mmio.mapper()) });
+
+        let mut config = Config::default();
+        config
+            .set_max_device_slots_enabled(4)
+            .set_device_context_base_address_array_pointer(base)
+            .set_command_ring_pointer(base + 0x400)
+            .set_event_ring_segment_table(base + 0x800, 1)
+            .set_event_ring_dequeue_pointer(base + 0xc00);
+
+        let mut ticks = 0;
+        let mut clock = || {
+            model.step();
+            ticks += 1;
+            Duration::from_millis(ticks)
+        };
+        assert_eq!(xhc.initialize(&config, &mut clock), Ok(()));
+
+        let r = xhc.registers();
+        assert!(!r.operational.usbsts.read_volatile().hc_halted());
+        assert_eq!(
+            r.operational
+                .config
+                .read_volatile()
+                .max_device_slots_enabled(),
+            4
+        );
+        assert_eq!(r.operational.dcbaap.read_volatile().get(), base);
+        assert!(r
+            .interrupter_register_set
+            .interrupter(0)
+            .iman
+            .read_volatile()
+            .interrupt_enable());
+    }
+
+    #[test]
+    fn reset_times_out() {
+        let mmio = Mmio::new();
+        let mut xhc = Controller::new(unsafe { Registers::new(mmio.base(), mmio.mapper()) });
+        xhc.set_timeout(Duration::from_millis(10));
+
+        // Nothing clears the Host Controller Reset bit.
+        let mut ticks = 0;
+        let mut clock = || {
+            ticks += 1;
+            Duration::from_millis(ticks)
+        };
+        assert_eq!(xhc.reset(&mut clock), Err(Error::ResetNotCompleted));
+
+        mmio.write_u32(OPERATIONAL_OFFSET + 0x04, 1 << 12);
+        assert_eq!(xhc.reset(&mut clock), Err(Error::HostControllerError));
+    }
+}
@@ -0,0 +1,171 @@
+//! Debug Capability Context.
+
+use crate::context::{Endpoint64Byte, EndpointHandler};
+use bit_field::BitField;
+use core::convert::TryInto;
+
+/// Debug Capability Context.
+///
+/// The Context consists of the DbC Info Context and the Endpoint Contexts of the OUT and IN
+/// endpoints. Unlike the Device Context, each of them is 64 bytes regardless of the Context Size
+/// field of HCCPARAMS1.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Context {
+    info: Info,
+    out: Endpoint64Byte,
+    input: Endpoint64Byte,
+}
+impl Context {
+    /// Creates an empty Debug Capability Context.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            info: Info::new(),
+            out: Endpoint64Byte::new_64byte(),
+            input: Endpoint64Byte::new_64byte(),
+        }
+    }
+
+    /// Returns the DbC Info Context.
+    #[must_use]
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// Returns the mutable reference to the DbC Info Context.
+    pub fn info_mut(&mut self) -> &mut Info {
+        &mut self.info
+    }
+
+    /// Returns a handler of the OUT Endpoint Context.
+    #[must_use]
+    pub fn out_endpoint(&self) -> &dyn EndpointHandler {
+        &self.out
+    }
+
+    /// Returns a mutable handler of the OUT Endpoint Context.
+    pub fn out_endpoint_mut(&mut self) -> &mut dyn EndpointHandler {
+        &mut self.out
+    }
+
+    /// Returns a handler of the IN Endpoint Context.
+    #[must_use]
+    pub fn in_endpoint(&self) -> &dyn EndpointHandler {
+        &self.input
+    }
+
+    /// Returns a mutable handler of the IN Endpoint Context.
+    pub fn in_endpoint_mut(&mut self) -> &mut dyn EndpointHandler {
+        &mut self.input
+    }
+}
+
+/// DbC Info Context.
+///
+/// The string descriptors pointed by this Context are returned to the debug host when it
+/// enumerates the DbC.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Info([u32; 16]);
+impl Info {
+    /// Creates an empty DbC Info Context.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self([0; 16])
+    }
+
+    /// Returns the value of the String 0 Descriptor Address field.
+    #[must_use]
+    pub fn string0_descriptor_address(self) -> u64 {
+        self.address(0)
+    }
+
+    /// Sets the value of the String 0 Descriptor Address field.
+    pub fn set_string0_descriptor_address(&mut self, a: u64) -> &mut Self {
+        self.set_address(0, a)
+    }
+
+    /// Returns the value of the Manufacturer String Descriptor Address field.
+    #[must_use]
+    pub fn manufacturer_string_descriptor_address(self) -> u64 {
+        self.address(2)
+    }
+
+    /// Sets the value of the Manufacturer String Descriptor Address field.
+    pub fn set_manufacturer_string_descriptor_address(&mut self, a: u64) -> &mut Self {
+        self.set_address(2, a)
+    }
+
+    /// Returns the value of the Product String Descriptor Address field.
+    #[must_use]
+    pub fn product_string_descriptor_address(self) -> u64 {
+        self.address(4)
+    }
+
+    /// Sets the value of the Product String Descriptor Address field.
+    pub fn set_product_string_descriptor_address(&mut self, a: u64) -> &mut Self {
+        self.set_address(4, a)
+    }
+
+    /// Returns the value of the Serial Number String Descriptor Address field.
+    #[must_use]
+    pub fn serial_number_string_descriptor_address(self) -> u64 {
+        self.address(6)
+    }
+
+    /// Sets the value of the Serial Number String Descriptor Address field.
+    pub fn set_serial_number_string_descriptor_address(&mut self, a: u64) -> &mut Self {
+        self.set_address(6, a)
+    }
+
+    rw_field!([8](0..=7), string0_length, "String 0 Length", u8);
+    rw_field!(
+        [8](8..=15),
+        manufacturer_string_length,
+        "Manufacturer String Length",
+        u8
+    );
+    rw_field!(
+        [8](16..=23),
+        product_string_length,
+        "Product String Length",
+        u8
+    );
+    rw_field!(
+        [8](24..=31),
+        serial_number_string_length,
+        "Serial Number String Length",
+        u8
+    );
+
+    fn address(self, i: usize) -> u64 {
+        let l: u64 = self.0[i].into();
+        let u: u64 = self.0[i + 1].into();
+
+        (u << 32) | l
+    }
+
+    fn set_address(&mut self, i: usize, a: u64) -> &mut Self {
+        self.0[i] = a.get_bits(0..32).try_into().unwrap();
+        self.0[i + 1] = a.get_bits(32..64).try_into().unwrap();
+        self
+    }
+}
+impl AsRef<[u32]> for Info {
+    fn as_ref(&self) -> &[u32] {
+        &self.0
+    }
+}
+impl_debug_from_methods! {
+    Info {
+        string0_descriptor_address,
+        manufacturer_string_descriptor_address,
+        product_string_descriptor_address,
+        serial_number_string_descriptor_address,
+        string0_length,
+        manufacturer_string_length,
+        product_string_length,
+        serial_number_string_length,
+    }
+}
@@ -0,0 +1,683 @@
+//! A driver of the Debug Capability.
+//!
+//! [`Dbc`] makes the xHC appear as a USB device with a pair of Bulk endpoints to a debug host
+//! connected to the debug port. Bytes written with [`Dbc::write`] are sent through the OUT
+//! endpoint, and those sent by the debug host are received with [`Dbc::read`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use core::time::Duration;
+//! use xhci::extended_capabilities::debug::dbc::{Buffer, Dbc, Memory};
+//! use xhci::extended_capabilities::debug::{context, Debug};
+//! use xhci::ring::{event, Segment};
+//!
+//! # fn run<M: xhci::accessor::Mapper + Clone>(registers: Debug<M>) {
+//! # let mut context = context::Context::new();
+//! # let mut table = [event::SegmentTableEntry::default()];
+//! # let mut event_memory = [[0; 4]; 16];
+//! # let mut out_memory = [[0; 4]; 16];
+//! # let mut in_memory = [[0; 4]; 16];
+//! # let mut out_bytes = [0; 512];
+//! # let mut in_bytes = [0; 1024];
+//! # let mut clock = || Duration::ZERO;
+//! let mut segments = [Segment::new(&mut event_memory, 0x1_0000)];
+//! let memory = Memory {
+//!     context: (&mut context, 0x2_0000),
+//!     event_ring: event::Ring::new(&mut segments),
+//!     segment_table: (&mut table, 0x3_0000),
+//!     out_ring: Segment::new(&mut out_memory, 0x4_0000),
+//!     in_ring: Segment::new(&mut in_memory, 0x5_0000),
+//!     out_buffer: Buffer::new(&mut out_bytes, 0x6_0000),
+//!     in_buffer: Buffer::new(&mut in_bytes, 0x7_0000),
+//! };
+//!
+//! let mut dbc = Dbc::new(registers, memory);
+//! dbc.enable(&mut clock).expect("No debug host is connected.");
+//!
+//! let mut message: &[u8] = b"Hello, world!\n";
+//! while !message.is_empty() {
+//!     let n = dbc.write(message).expect("Failed to write.");
+//!     message = &message[n..];
+//! }
+//! # }
+//! ```
+
+use super::context::Context;
+use super::Debug;
+use crate::context::{EndpointHandler, EndpointType};
+use crate::controller::{Clock, DEFAULT_TIMEOUT};
+use crate::registers::runtime::EventRingDequeuePointerRegister;
+use crate::ring::trb::event::{Allowed, CompletionCode, TransferEvent};
+use crate::ring::trb::transfer::Normal;
+use crate::ring::{event, transfer, Segment};
+use accessor::Mapper;
+use core::convert::TryFrom;
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+use core::time::Duration;
+
+/// The maximum packet size of the Bulk endpoints of the DbC.
+pub const MAX_PACKET_SIZE: u16 = 1024;
+
+// The Endpoint IDs reported in the Transfer Events of the DbC.
+const OUT_ENDPOINT_ID: u8 = 2;
+const IN_ENDPOINT_ID: u8 = 3;
+
+// The values of the Doorbell Target field of the DbC Doorbell Register.
+const OUT_DOORBELL_TARGET: u8 = 0;
+const IN_DOORBELL_TARGET: u8 = 1;
+
+/// The memory used by [`Dbc`].
+///
+/// All the memory must be accessible by the xHC, and each physical address must be that of the
+/// memory paired with it.
+#[derive(Debug)]
+pub struct Memory<'a> {
+    /// The Debug Capability Context and its physical address.
+    ///
+    /// The DbC Info Context is left as is, so set the string descriptors beforehand.
+    pub context: (&'a mut Context, u64),
+    /// The Event Ring.
+    pub event_ring: event::Ring<'a, 'a>,
+    /// The Event Ring Segment Table and its physical address.
+    ///
+    /// The table must have as many entries as the segments of the Event Ring.
+    pub segment_table: (&'a mut [event::SegmentTableEntry], u64),
+    /// The segment of the Transfer Ring of the OUT endpoint.
+    pub out_ring: Segment<'a>,
+    /// The segment of the Transfer Ring of the IN endpoint.
+    pub in_ring: Segment<'a>,
+    /// The buffer which holds the bytes being sent.
+    pub out_buffer: Buffer<'a>,
+    /// The buffer which receives the bytes from the debug host.
+    ///
+    /// The length must be a multiple of [`MAX_PACKET_SIZE`]. Otherwise the debug host may send a
+    /// packet which does not fit in the buffer, and the DbC halts the IN endpoint with a Babble
+    /// error.
+    pub in_buffer: Buffer<'a>,
+}
+
+/// A data buffer of a transfer.
+///
+/// The memory must be accessible by the xHC. The bytes are read and written with volatile
+/// accesses.
+#[derive(Debug)]
+pub struct Buffer<'a> {
+    bytes: &'a mut [u8],
+    phys_base: u64,
+}
+impl<'a> Buffer<'a> {
+    /// Creates a new Buffer.
+    ///
+    /// `bytes` is the memory of the Buffer, and `phys_base` is the physical address of `bytes[0]`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `bytes` is empty, or if the Buffer crosses a 64KB boundary.
+    pub fn new(bytes: &'a mut [u8], phys_base: u64) -> Self {
+        assert!(!bytes.is_empty(), "A Buffer must not be empty.");
+
+        let len = u64::try_from(bytes.len()).unwrap();
+        assert_eq!(
+            phys_base >> 16,
+            (phys_base + len - 1) >> 16,
+            "A Buffer must not cross a 64KB boundary."
+        );
+
+        Self { bytes, phys_base }
+    }
+
+    /// Returns the physical address of the first byte of this Buffer.
+    #[must_use]
+    pub fn phys_base(&self) -> u64 {
+        self.phys_base
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn read(&self, range: Range<usize>, dst: &mut [u8]) {
+        for (d, s) in dst.iter_mut().zip(&self.bytes[range]) {
+            // SAFETY: `s` is a valid reference.
+            *d = unsafe { ptr::read_volatile(s) };
+        }
+    }
+
+    fn write(&mut self, src: &[u8]) {
+        for (d, s) in self.bytes.iter_mut().zip(src) {
+            // SAFETY: `d` is a valid reference.
+            unsafe { ptr::write_volatile(d, *s) };
+        }
+    }
+}
+
+/// A driver of the Debug Capability.
+///
+/// Call [`Dbc::enable`] to start the DbC. [`Dbc::write`] and [`Dbc::read`] do not block, and handle
+/// the events written by the DbC before transferring bytes.
+#[derive(Debug)]
+pub struct Dbc<'a, M>
+where
+    M: Mapper + Clone,
+{
+    registers: Debug<M>,
+    context_phys: u64,
+    segment_table: (u64, u16),
+    event_ring: event::Ring<'a, 'a>,
+    out: Pipe<'a>,
+    input: Pipe<'a>,
+    timeout: Duration,
+}
+impl<'a, M> Dbc<'a, M>
+where
+    M: Mapper + Clone,
+{
+    /// Creates a new driver.
+    ///
+    /// This method writes the Event Ring Segment Table and the Endpoint Contexts, but does not
+    /// enable the DbC.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the physical address of the Context or the Event Ring Segment Table
+    /// is not 16-byte aligned, if the Event Ring has more segments than the DbC supports, or if the
+    /// length of the IN buffer is not a multiple of [`MAX_PACKET_SIZE`].
+    pub fn new(registers: Debug<M>, memory: Memory<'a>) -> Self {
+        let Memory {
+            context: (context, context_phys),
+            event_ring,
+            segment_table: (table, table_phys),
+            out_ring,
+            in_ring,
+            out_buffer,
+            in_buffer,
+        } = memory;
+
+        assert!(
+            context_phys.trailing_zeros() >= 4,
+            "The Debug Capability Context must be 16-byte aligned."
+        );
+        assert!(
+            table_phys.trailing_zeros() >= 4,
+            "The Event Ring Segment Table must be 16-byte aligned."
+        );
+        assert_eq!(
+            in_buffer.len() % usize::from(MAX_PACKET_SIZE),
+            0,
+            "The length of the IN buffer must be a multiple of the maximum packet size."
+        );
+
+        let erst_max = registers
+            .dcid
+            .read_volatile()
+            .debug_capability_event_ring_segment_table_max();
+        assert!(
+            u32::from(event_ring.segment_table_size()) <= 1 << erst_max,
+            "The Event Ring has more segments than the DbC supports."
+        );
+        event_ring.write_segment_table(table);
+
+        let max_burst_size = registers.dcctrl.read_volatile().debug_max_burst_size();
+        let out = Pipe::new(out_ring, out_buffer);
+        let input = Pipe::new(in_ring, in_buffer);
+
+        let mut c = Context::new();
+        *c.info_mut() = *context.info();
+        out.initialize_endpoint_context(
+            c.out_endpoint_mut(),
+            (EndpointType::BulkOut, max_burst_size),
+        );
+        input.initialize_endpoint_context(
+            c.in_endpoint_mut(),
+            (EndpointType::BulkIn, max_burst_size),
+        );
+
+        // SAFETY: `context` is a valid reference.
+        unsafe { ptr::write_volatile(context, c) };
+
+        Self {
+            registers,
+            context_phys,
+            segment_table: (table_phys, event_ring.segment_table_size()),
+            event_ring,
+            out,
+            input,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Returns the time to wait for the debug host to configure the DbC.
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time to wait for the debug host to configure the DbC.
+    ///
+    /// The default value is [`DEFAULT_TIMEOUT`].
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the accessor to the Debug Capability registers.
+    #[must_use]
+    pub fn registers(&self) -> &Debug<M> {
+        &self.registers
+    }
+
+    /// Enables the DbC and waits until the debug host configures it.
+    ///
+    /// This method writes the registers pointing to the Event Ring and the Context, sets the Debug
+    /// Capability Enable bit and the Port Enabled/Disabled bit, and waits for the DbC Run bit.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`Error::NotRunning`] if the DbC is not configured within the timeout.
+    /// The DbC stays enabled in this case, and [`Dbc::is_running`] returns `true` once a debug
+    /// host configures it.
+    pub fn enable(&mut self, clock: &mut dyn Clock) -> Result<(), Error> {
+        let (table_phys, table_size) = self.segment_table;
+        let r = &mut self.registers;
+
+        r.dcerstsz.update_volatile(|s| s.set(table_size));
+        r.dcerstba.update_volatile(|b| b.set(table_phys));
+        self.update_erdp();
+
+        let r = &mut self.registers;
+        r.dccp.update_volatile(|c| c.set(self.context_phys));
+        r.dcctrl.update_volatile(|c| {
+            c.set_debug_capability_enable();
+        });
+        r.dcportsc.update_volatile(|p| {
+            p.set_port_enabled_disabled();
+        });
+
+        let start = clock.now();
+        while !self.is_running() {
+            if clock.now().saturating_sub(start) >= self.timeout {
+                return Err(Error::NotRunning);
+            }
+        }
+
+        self.registers.dcctrl.update_volatile(|c| {
+            c.clear_dbc_run_change();
+        });
+
+        Ok(())
+    }
+
+    /// Disables the DbC and returns the accessor to the registers.
+    #[must_use]
+    pub fn disable(mut self) -> Debug<M> {
+        self.registers.dcctrl.update_volatile(|c| {
+            c.clear_debug_capability_enable();
+        });
+
+        self.registers
+    }
+
+    /// Returns `true` if the debug host has configured the DbC.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        self.registers.dcctrl.read_volatile().dbc_run()
+    }
+
+    /// Sends the bytes to the debug host, and returns the number of bytes accepted.
+    ///
+    /// Only one transfer is in flight at a time, so this method returns `Ok(0)` until the
+    /// previous one completes. The number of bytes accepted at once is limited by the size of
+    /// the OUT buffer.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the DbC is not running, if a transfer has failed, or if the
+    /// OUT endpoint is halted.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<usize, Error> {
+        self.poll()?;
+
+        if !self.is_running() {
+            return Err(Error::NotRunning);
+        }
+
+        if self.out.halted {
+            return Err(Error::Halted);
+        }
+
+        if self.out.busy || bytes.is_empty() {
+            return Ok(0);
+        }
+
+        let n = bytes.len().min(self.out.buffer.len());
+        self.out.buffer.write(&bytes[..n]);
+        self.out.start(n);
+        self.ring_doorbell(OUT_DOORBELL_TARGET);
+
+        Ok(n)
+    }
+
+    /// Copies the bytes received from the debug host to `buf`, and returns the number of bytes
+    /// copied.
+    ///
+    /// This method returns `Ok(0)` if no bytes have been received yet. A transfer on the IN
+    /// endpoint is started once all the received bytes are read.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the DbC is not running, if a transfer has failed, or if the
+    /// IN endpoint is halted.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.poll()?;
+
+        if !self.is_running() {
+            return Err(Error::NotRunning);
+        }
+
+        if self.input.halted {
+            return Err(Error::Halted);
+        }
+
+        let completed = self.input.completed.clone();
+        let n = completed.len().min(buf.len());
+        self.input
+            .buffer
+            .read(completed.start..completed.start + n, buf);
+        self.input.completed.start += n;
+
+        if self.input.completed.is_empty() && !self.input.busy {
+            let len = self.input.buffer.len();
+            self.input.start(len);
+            self.ring_doorbell(IN_DOORBELL_TARGET);
+        }
+
+        Ok(n)
+    }
+
+    /// Handles the events written by the DbC.
+    ///
+    /// [`Dbc::write`] and [`Dbc::read`] call this method, so it needs to be called only to
+    /// acknowledge the port status changes without transferring bytes.
+    ///
+    /// A transfer which fails with a Stall or Babble error halts the endpoint. This method also
+    /// checks the Halt OUT TR and Halt IN TR bits, and resumes the endpoint once the debug host
+    /// clears the halt.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`Error::Transfer`] if a transfer has failed. All the events are
+    /// handled even in this case.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+
+        while let Some(e) = self.event_ring.next() {
+            match e {
+                Ok(Allowed::TransferEvent(t)) => {
+                    if let Err(e) = self.handle_transfer_event(&t) {
+                        result = Err(e);
+                    }
+                }
+                Ok(Allowed::PortStatusChange(_)) => {
+                    // Writing back the read value clears the change bits.
+                    self.registers.dcportsc.update_volatile(|_| {});
+                }
+                _ => {}
+            }
+        }
+
+        self.update_erdp();
+
+        let c = self.registers.dcctrl.read_volatile();
+        self.out.halted &= c.halt_out_tr();
+        self.input.halted &= c.halt_in_tr();
+
+        result
+    }
+
+    fn handle_transfer_event(&mut self, e: &TransferEvent) -> Result<(), Error> {
+        let pipe = match e.endpoint_id() {
+            OUT_ENDPOINT_ID => &mut self.out,
+            IN_ENDPOINT_ID => &mut self.input,
+            _ => return Ok(()),
+        };
+
+        let code = e.completion_code();
+
+        // The DbC retries the halted TD once the debug host clears the halt, so it stays in
+        // flight.
+        if let Ok(CompletionCode::StallError | CompletionCode::BabbleDetectedError) = code {
+            pipe.halted = true;
+            return Err(Error::Transfer(code));
+        }
+
+        pipe.ring.handle_transfer_event(e);
+        pipe.busy = false;
+
+        atomic::fence(Ordering::Acquire);
+
+        match code {
+            Ok(CompletionCode::Success | CompletionCode::ShortPacket) => {
+                let residual = usize::try_from(e.trb_transfer_length()).unwrap();
+                pipe.completed = 0..pipe.requested.saturating_sub(residual);
+                Ok(())
+            }
+            code => Err(Error::Transfer(code)),
+        }
+    }
+
+    fn update_erdp(&mut self) {
+        let mut e = EventRingDequeuePointerRegister::default();
+        self.event_ring.update_erdp(&mut e);
+
+        self.registers.dcerdp.update_volatile(|d| {
+            d.set_dequeue_pointer(e.event_ring_dequeue_pointer());
+            d.set_dequeue_erst_segment_index(e.dequeue_erst_segment_index());
+        });
+    }
+
+    fn ring_doorbell(&mut self, target: u8) {
+        self.registers.dcdb.update_volatile(|d| {
+            d.set_doorbell_target(target);
+        });
+    }
+}
+
+/// Errors returned by [`Dbc`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum Error {
+    /// The DbC is not configured by a debug host.
+    NotRunning,
+    /// A transfer completed with an error.
+    ///
+    /// The value is that of the Completion Code field of the Transfer Event TRB.
+    Transfer(Result<CompletionCode, u8>),
+    /// The endpoint is halted.
+    ///
+    /// The debug host needs to clear the halt with a `ClearFeature(ENDPOINT_HALT)` request.
+    Halted,
+}
+
+/// A Transfer Ring and a data buffer of an endpoint.
+///
+/// `completed` is the range of the bytes transferred by the last transfer which are not consumed
+/// yet. `halted` is `true` from a Stall or Babble error until the debug host clears the halt.
+#[derive(Debug)]
+struct Pipe<'a> {
+    ring: transfer::Ring<'a, 1>,
+    buffer: Buffer<'a>,
+    busy: bool,
+    halted: bool,
+    requested: usize,
+    completed: Range<usize>,
+}
+impl<'a> Pipe<'a> {
+    fn new(segment: Segment<'a>, buffer: Buffer<'a>) -> Self {
+        Self {
+            ring: transfer::Ring::new(segment),
+            buffer,
+            busy: false,
+            halted: false,
+            requested: 0,
+            completed: 0..0,
+        }
+    }
+
+    fn initialize_endpoint_context(
+        &self,
+        ep: &mut dyn EndpointHandler,
+        (ty, max_burst_size): (EndpointType, u8),
+    ) {
+        ep.set_endpoint_type(ty);
+        ep.set_error_count(3);
+        ep.set_max_packet_size(MAX_PACKET_SIZE);
+        ep.set_max_burst_size(max_burst_size);
+        ep.set_average_trb_length(MAX_PACKET_SIZE);
+        self.ring.initialize_endpoint_context(ep);
+    }
+
+    fn start(&mut self, len: usize) {
+        let mut normal = Normal::new();
+        normal
+            .set_data_buffer_pointer(self.buffer.phys_base())
+            .set_trb_transfer_length(u32::try_from(len).unwrap())
+            .set_interrupt_on_short_packet()
+            .set_interrupt_on_completion();
+
+        atomic::fence(Ordering::Release);
+
+        // Only one TD is in flight, so the ring always has space for it.
+        self.ring
+            .enqueue(&[normal.into()])
+            .expect("The Transfer Ring is full.");
+
+        self.busy = true;
+        self.requested = len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::Mmio;
+    use core::slice;
+
+    const CONTEXT: usize = 0x1000;
+    const TABLE: usize = 0x1100;
+    const EVENT_RING: usize = 0x2000;
+    const OUT_RING: usize = 0x2400;
+    const IN_RING: usize = 0x2800;
+    const OUT_BUFFER: usize = 0x3000;
+    const IN_BUFFER: usize = 0x3400;
+
+    fn transfer_event(ring: u64, (code, residual): (u32, u32), endpoint_id: u32) -> [u32; 4] {
+        [
+            u32::try_from(ring & 0xffff_ffff).unwrap(),
+            u32::try_from(ring >> 32).unwrap(),
+            code << 24 | residual,
+            endpoint_id << 16 | 32 << 10 | 1,
+        ]
+    }
+
+    #[test]
+    fn echo_with_emulated_dbc() {
+        let mmio = Mmio::zeroed(0x4000);
+        let base = u64::try_from(mmio.base()).unwrap();
+        let phys = |offset: usize| base + u64::try_from(offset).unwrap();
+
+        let trbs = |offset: usize| {
+            // SAFETY: The TRBs are inside `mmio`, and the ranges do not overlap.
+            unsafe { slice::from_raw_parts_mut((mmio.base() + offset) as *mut [u32; 4], 16) }
+        };
+        let bytes = |offset: usize, len: usize| {
+            // SAFETY: The bytes are inside `mmio`, and the ranges do not overlap.
+            unsafe { slice::from_raw_parts_mut((mmio.base() + offset) as *mut u8, len) }
+        };
+
+        // SAFETY: The context and the table are inside `mmio`, and they do not overlap.
+        let context = unsafe { &mut *((mmio.base() + CONTEXT) as *mut Context) };
+        let table = unsafe {
+            slice::from_raw_parts_mut((mmio.base() + TABLE) as *mut event::SegmentTableEntry, 1)
+        };
+        context.info_mut().set_string0_descriptor_address(0x1234);
+
+        let mut segments = [Segment::new(trbs(EVENT_RING), phys(EVENT_RING))];
+        let memory = Memory {
+            context: (context, phys(CONTEXT)),
+            event_ring: event::Ring::new(&mut segments),
+            segment_table: (table, phys(TABLE)),
+            out_ring: Segment::new(trbs(OUT_RING), phys(OUT_RING)),
+            in_ring: Segment::new(trbs(IN_RING), phys(IN_RING)),
+            out_buffer: Buffer::new(bytes(OUT_BUFFER, 64), phys(OUT_BUFFER)),
+            in_buffer: Buffer::new(bytes(IN_BUFFER, 1024), phys(IN_BUFFER)),
+        };
+
+        // SAFETY: The registers are at the start of `mmio`.
+        let registers = unsafe { Debug::new(mmio.base(), &mmio.mapper()) };
+        let mut dbc = Dbc::new(registers, memory);
+
+        // The DbC Info Context is kept, and the Endpoint Contexts point to the rings.
+        assert_eq!(mmio.read_u64(CONTEXT), 0x1234);
+        assert_eq!(mmio.read_u32(CONTEXT + 0x44), 2 << 3 | 3 << 1 | 1024 << 16);
+        assert_eq!(mmio.read_u64(CONTEXT + 0x48), phys(OUT_RING) | 1);
+        assert_eq!(mmio.read_u32(CONTEXT + 0x84), 6 << 3 | 3 << 1 | 1024 << 16);
+        assert_eq!(mmio.read_u64(TABLE), phys(EVENT_RING));
+
+        // The debug host configures the DbC once it is enabled.
+        let mut clock = || {
+            let c = mmio.read_u32(0x20);
+            if c & 1 << 31 != 0 {
+                mmio.write_u32(0x20, c | 1 << 4 | 1);
+            }
+            Duration::ZERO
+        };
+        assert_eq!(dbc.enable(&mut clock), Ok(()));
+        assert_eq!(mmio.read_u64(0x10), phys(TABLE));
+        assert_eq!(mmio.read_u64(0x18), phys(EVENT_RING));
+        assert_eq!(mmio.read_u64(0x30), phys(CONTEXT));
+        assert_eq!(mmio.read_u32(0x28) & 0b10, 0b10);
+
+        assert_eq!(dbc.write(b"ping"), Ok(4));
+        assert_eq!(dbc.write(b"pong"), Ok(0));
+        assert_eq!(mmio.read_u64(OUT_RING), phys(OUT_BUFFER));
+        assert_eq!(mmio.read_u32(OUT_RING + 8), 4);
+        assert_eq!(mmio.read_u32(OUT_BUFFER), u32::from_le_bytes(*b"ping"));
+
+        trbs(EVENT_RING)[0] = transfer_event(phys(OUT_RING), (1, 0), 2);
+
+        let mut buf = [0; 8];
+        assert_eq!(dbc.read(&mut buf), Ok(0));
+        assert_eq!(mmio.read_u64(0x18), phys(EVENT_RING + 0x10));
+        assert_eq!(mmio.read_u64(IN_RING), phys(IN_BUFFER));
+        assert_eq!(mmio.read_u32(IN_RING + 8), 1024);
+
+        // The debug host echoes the bytes back with a short packet.
+        mmio.write_u32(IN_BUFFER, u32::from_le_bytes(*b"ping"));
+        trbs(EVENT_RING)[1] = transfer_event(phys(IN_RING), (13, 1020), 3);
+
+        assert_eq!(dbc.read(&mut buf[..3]), Ok(3));
+        assert_eq!(dbc.read(&mut buf[3..]), Ok(1));
+        assert_eq!(&buf[..4], b"ping");
+        assert_eq!(mmio.read_u64(IN_RING + 0x10), phys(IN_BUFFER));
+
+        // A stalled transfer is reported, and the OUT endpoint stays halted until the debug host
+        // clears the halt.
+        assert_eq!(dbc.write(b"pong"), Ok(4));
+        trbs(EVENT_RING)[2] = transfer_event(phys(OUT_RING + 0x10), (6, 4), 2);
+        mmio.write_u32(0x20, mmio.read_u32(0x20) | 1 << 2);
+        assert_eq!(
+            dbc.write(b"pong"),
+            Err(Error::Transfer(Ok(CompletionCode::StallError)))
+        );
+        assert_eq!(dbc.write(b"pong"), Err(Error::Halted));
+
+        // The DbC retries the TD after the halt is cleared.
+        mmio.write_u32(0x20, mmio.read_u32(0x20) & !(1 << 2));
+        assert_eq!(dbc.write(b"pong"), Ok(0));
+
+        trbs(EVENT_RING)[3] = transfer_event(phys(OUT_RING + 0x10), (1, 0), 2);
+        assert_eq!(dbc.write(b"pong"), Ok(4));
+        assert_eq!(mmio.read_u64(OUT_RING + 0x20), phys(OUT_BUFFER));
+    }
+}
@@ -1,10 +1,15 @@
 //! Debug Capability.
 
 use super::ExtendedCapability;
+use crate::registers::operational::PortLinkState;
 use accessor::single;
 use accessor::Mapper;
 use bit_field::BitField;
 use core::convert::TryInto;
+use num_traits::FromPrimitive;
+
+pub mod context;
+pub mod dbc;
 
 /// The entry point to the Debug Capability.
 #[derive(Debug)]
@@ -274,9 +279,11 @@ impl PortStatusAndControl {
     ro_bit!(4, port_reset, "Port Reset");
 
     /// Returns the value of the Port Link State field.
+    ///
+    /// This field returns [`None`] if the value means `Reserved`.
     #[must_use]
-    pub fn port_link_state(self) -> u8 {
-        self.0.get_bits(5..=8).try_into().unwrap()
+    pub fn port_link_state(self) -> Option<PortLinkState> {
+        FromPrimitive::from_u32(self.0.get_bits(5..=8))
     }
 
     /// Returns the value of the Port Speed field.
@@ -1,7 +1,9 @@
 //! USB Legacy Support Capability
 
-use super::ExtendedCapability;
+use super::{ExtendedCapability, List};
+use crate::controller::Clock;
 use accessor::{single, Mapper};
+use core::time::Duration;
 
 /// USB Legacy Support Capability.
 #[derive(Debug)]
@@ -31,13 +33,51 @@ where
     /// This method panics if `base` is not aligned correctly.
     pub unsafe fn new(base: usize, m: M) -> Self {
         let usblegsup = single::ReadWrite::new(base, m.clone());
-        let usblegctlsts = single::ReadWrite::new(base, m);
+        let usblegctlsts = single::ReadWrite::new(base + 4, m);
 
         Self {
             usblegsup,
             usblegctlsts,
         }
     }
+
+    /// Takes the ownership of the xHC from the BIOS.
+    ///
+    /// This method sets the HC OS Owned Semaphore bit and waits until the BIOS clears the HC BIOS
+    /// Owned Semaphore bit. If the BIOS does not clear it within `timeout`, this method clears it
+    /// instead. After that, all the SMIs are disabled and the SMI status bits are cleared.
+    pub fn take_ownership(&mut self, clock: &mut dyn Clock, timeout: Duration) -> Handoff {
+        self.usblegsup.update_volatile(|l| {
+            l.set_hc_os_owned_semaphore();
+        });
+
+        let start = clock.now();
+        let handoff = loop {
+            if !self.usblegsup.read_volatile().hc_bios_owned_semaphore() {
+                break Handoff::Released;
+            }
+
+            if clock.now().saturating_sub(start) >= timeout {
+                self.usblegsup.update_volatile(|l| {
+                    l.clear_hc_bios_owned_semaphore();
+                });
+                break Handoff::Forced;
+            }
+        };
+
+        self.usblegctlsts.update_volatile(|c| {
+            c.clear_usb_smi_enable()
+                .clear_smi_on_host_system_error_enable()
+                .clear_smi_on_os_ownership_enable()
+                .clear_smi_on_pci_command_enable()
+                .clear_smi_on_bar_enable()
+                .clear_smi_on_os_ownership_change()
+                .clear_smi_on_pci_command()
+                .clear_smi_on_bar();
+        });
+
+        handoff
+    }
 }
 impl<M> From<UsbLegacySupport<M>> for ExtendedCapability<M>
 where
@@ -48,6 +88,35 @@ where
     }
 }
 
+/// Finds the USB Legacy Support Capability in `list` and takes the ownership of the xHC from the
+/// BIOS with [`UsbLegacySupport::take_ownership`].
+///
+/// This function returns [`None`] if the xHC does not have the USB Legacy Support Capability.
+pub fn take_ownership<M>(
+    list: &mut List<M>,
+    clock: &mut dyn Clock,
+    timeout: Duration,
+) -> Option<Handoff>
+where
+    M: Mapper + Clone,
+{
+    list.into_iter()
+        .find_map(|c| match c {
+            Ok(ExtendedCapability::UsbLegacySupport(u)) => Some(u),
+            _ => None,
+        })
+        .map(|mut u| u.take_ownership(clock, timeout))
+}
+
+/// The result of the BIOS-to-OS ownership handoff.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum Handoff {
+    /// The BIOS released the ownership, or the BIOS did not own the xHC.
+    Released,
+    /// The BIOS did not release the ownership in time, and the OS took it forcibly.
+    Forced,
+}
+
 /// The first 4-byte of the USB Legacy Support Capability.
 #[repr(transparent)]
 #[derive(Copy, Clone)]
@@ -97,3 +166,66 @@ impl_debug_from_methods! {
         smi_on_bar,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{Mmio, EXTENDED_CAPABILITIES_OFFSET};
+    use crate::Registers;
+
+    const LEGSUP: usize = EXTENDED_CAPABILITIES_OFFSET;
+    const LEGCTLSTS: usize = EXTENDED_CAPABILITIES_OFFSET + 4;
+
+    fn list(mmio: &Mmio) -> List<crate::mock::Mapper> {
+        let r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+
+        unsafe {
+            List::new(
+                mmio.base(),
+                r.capability.hccparams1.read_volatile(),
+                mmio.mapper(),
+            )
+        }
+        .unwrap()
+    }
+
+    #[test]
+    fn bios_releases_ownership() {
+        let mmio = Mmio::new();
+        mmio.write_u32(LEGSUP, mmio.read_u32(LEGSUP) | 1 << 16);
+        mmio.write_u32(LEGCTLSTS, 0xe000_e011);
+
+        // The BIOS releases the ownership once the OS requests it.
+        let mut clock = || {
+            let v = mmio.read_u32(LEGSUP);
+            if v & 1 << 24 != 0 {
+                mmio.write_u32(LEGSUP, v & !(1 << 16));
+            }
+            Duration::ZERO
+        };
+        assert_eq!(
+            take_ownership(&mut list(&mmio), &mut clock, Duration::from_secs(1)),
+            Some(Handoff::Released)
+        );
+        assert_eq!(mmio.read_u32(LEGSUP) >> 16, 0x100);
+        // The enable bits are cleared, and 1 is written to the RW1C bits.
+        assert_eq!(mmio.read_u32(LEGCTLSTS), 0xe000_0000);
+    }
+
+    #[test]
+    fn forced_takeover() {
+        let mmio = Mmio::new();
+        mmio.write_u32(LEGSUP, mmio.read_u32(LEGSUP) | 1 << 16);
+
+        let mut ticks = 0;
+        let mut clock = || {
+            ticks += 1;
+            Duration::from_millis(ticks)
+        };
+        assert_eq!(
+            take_ownership(&mut list(&mmio), &mut clock, Duration::from_millis(10)),
+            Some(Handoff::Forced)
+        );
+        assert_eq!(mmio.read_u32(LEGSUP) >> 16, 0x100);
+    }
+}
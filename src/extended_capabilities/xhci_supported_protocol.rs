@@ -3,7 +3,8 @@
 use super::ExtendedCapability;
 use accessor::{array, single, Mapper};
 use bit_field::BitField;
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
+use core::ops::RangeInclusive;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
@@ -45,6 +46,17 @@ where
 
         Self { header, psis }
     }
+
+    /// Returns an iterator over the Protocol Speed ID Dwords.
+    ///
+    /// The iterator yields as many values as the Protocol Speed ID Count field.
+    #[must_use]
+    pub fn protocol_speed_ids(&self) -> ProtocolSpeedIds<'_, M> {
+        ProtocolSpeedIds {
+            psis: self.psis.as_ref(),
+            index: 0,
+        }
+    }
 }
 impl<M> From<XhciSupportedProtocol<M>> for ExtendedCapability<M>
 where
@@ -78,6 +90,34 @@ impl Header {
         self.0[1]
     }
 
+    /// Returns the decoded Name String field, which is `"USB "` for the USB protocols.
+    ///
+    /// # Errors
+    ///
+    /// This method returns the raw value if the field contains a character which is not a
+    /// printable ASCII character.
+    pub fn name(self) -> Result<NameString, u32> {
+        NameString::try_from(self.0[1])
+    }
+
+    /// Returns the range of the port numbers which are compatible with this protocol.
+    ///
+    /// The range is empty if the Compatible Port Count field is 0.
+    #[must_use]
+    pub fn ports(self) -> RangeInclusive<u8> {
+        let first = self.compatible_port_offset();
+
+        match self.compatible_port_count() {
+            0 => {
+                // An exhausted range is empty and contains no port.
+                let mut r = first..=first;
+                r.next();
+                r
+            }
+            n => first..=first.saturating_add(n - 1),
+        }
+    }
+
     /// Returns the value of the Compatible Port Offset field.
     #[must_use]
     pub fn compatible_port_offset(self) -> u8 {
@@ -166,6 +206,71 @@ impl_debug_from_methods! {
     }
 }
 
+/// The decoded Name String field of the xHCI Supported Protocol Capability.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NameString([u8; 4]);
+impl NameString {
+    /// Returns the Name String as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).expect("The Name String must consist of ASCII characters.")
+    }
+
+    /// Returns `true` if the Name String is `"USB "`.
+    #[must_use]
+    pub fn is_usb(&self) -> bool {
+        &self.0 == b"USB "
+    }
+}
+impl TryFrom<u32> for NameString {
+    type Error = u32;
+
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        let b = v.to_le_bytes();
+
+        if b.iter().all(|c| *c == b' ' || c.is_ascii_graphic()) {
+            Ok(Self(b))
+        } else {
+            Err(v)
+        }
+    }
+}
+impl AsRef<str> for NameString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// An iterator over the Protocol Speed ID Dwords.
+///
+/// [`XhciSupportedProtocol::protocol_speed_ids`] returns a value of this type.
+#[derive(Debug)]
+pub struct ProtocolSpeedIds<'a, M>
+where
+    M: Mapper,
+{
+    psis: Option<&'a array::ReadWrite<ProtocolSpeedId, M>>,
+    index: usize,
+}
+impl<M> Iterator for ProtocolSpeedIds<'_, M>
+where
+    M: Mapper,
+{
+    type Item = ProtocolSpeedId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let psis = self.psis?;
+
+        if self.index >= psis.len() {
+            return None;
+        }
+
+        let psi = psis.read_volatile_at(self.index);
+        self.index += 1;
+        Some(psi)
+    }
+}
+
 /// Protocol Speed ID
 #[repr(transparent)]
 #[derive(Copy, Clone)]
@@ -187,8 +292,13 @@ impl ProtocolSpeedId {
     /// Returns the value of the PSI Type field.
     #[must_use]
     pub fn psi_type(self) -> PsiType {
-        let r = FromPrimitive::from_u32(self.0.get_bits(6..=7));
-        r.expect("The PSI Type must not take the reserved value.")
+        self.try_psi_type()
+            .expect("The PSI Type must not take the reserved value.")
+    }
+
+    /// Returns the value of the PSI Type field, or [`None`] if it is reserved.
+    pub(crate) fn try_psi_type(self) -> Option<PsiType> {
+        FromPrimitive::from_u32(self.0.get_bits(6..=7))
     }
 
     /// Returns the PSI Full-duplex bit.
@@ -265,3 +375,34 @@ pub enum LinkProtocol {
     /// Super Speed Plus
     SuperSpeedPlus = 1,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::{Mmio, EXTENDED_CAPABILITIES_OFFSET};
+
+    #[test]
+    fn decode_header_and_psis() {
+        let mmio = Mmio::new();
+        let x = EXTENDED_CAPABILITIES_OFFSET + 0x20;
+        mmio.write_u32(x + 8, mmio.read_u32(x + 8) | 2 << 28);
+        mmio.write_u32(x + 0x10, 5 << 16 | 2 << 4 | 4);
+        mmio.write_u32(x + 0x14, 10 << 16 | 3 << 4 | 5);
+
+        let p = unsafe { XhciSupportedProtocol::new(mmio.base() + x, mmio.mapper()) };
+        let h = p.header.read_volatile();
+
+        let name = h.name().unwrap();
+        assert!(name.is_usb());
+        assert_eq!(name.as_str(), "USB ");
+        assert_eq!(h.ports(), 3..=4);
+
+        let values: [u8; 2] = [4, 5];
+        assert!(p
+            .protocol_speed_ids()
+            .map(ProtocolSpeedId::protocol_speed_id_value)
+            .eq(values.iter().copied()));
+
+        assert_eq!(NameString::try_from(0x0042_5355), Err(0x0042_5355));
+    }
+}
@@ -8,6 +8,8 @@
 //! # Examples
 //!
 //! ```no_run
+//! use core::time::Duration;
+//! use xhci::controller::{Config, Controller};
 //! # use core::num::NonZeroUsize;
 //! # use xhci::accessor::Mapper;
 //! #
@@ -24,16 +26,27 @@
 //! #         unimplemented!()
 //! #     }
 //! # }
+//! # fn timer_now() -> Duration {
+//! #     unimplemented!()
+//! # }
 //! #
 //! # let mapper = MemoryMapper;
+//! # let (dcbaa, command_ring, erst, event_ring) = (0x1_0000, 0x2_0000, 0x3_0000, 0x4_0000);
 //! #
-//! let mut r = unsafe { xhci::Registers::new(MMIO_BASE, mapper) };
-//! let o = &mut r.operational;
+//! let r = unsafe { xhci::Registers::new(MMIO_BASE, mapper) };
+//! let mut xhc = Controller::new(r);
+//!
+//! let mut config = Config::default();
+//! config
+//!     .set_max_device_slots_enabled(8)
+//!     .set_device_context_base_address_array_pointer(dcbaa)
+//!     .set_command_ring_pointer(command_ring)
+//!     .set_event_ring_segment_table(erst, 1)
+//!     .set_event_ring_dequeue_pointer(event_ring);
 //!
-//! o.usbcmd.update(|u| {
-//!     u.set_run_stop();
-//! });
-//! while o.usbsts.read().hc_halted() {}
+//! // Resets the xHC, writes `config` to the registers, and starts the xHC.
+//! xhc.initialize(&config, &mut timer_now)
+//!     .expect("Failed to initialize the xHC.");
 //! ```
 
 #![no_std]
@@ -59,6 +72,9 @@
 )]
 #![allow(clippy::missing_panics_doc)]
 
+#[cfg(any(test, feature = "mock"))]
+extern crate alloc;
+
 pub use accessor;
 pub use extended_capabilities::ExtendedCapability;
 pub use registers::Registers;
@@ -67,6 +83,11 @@ pub use registers::Registers;
 mod macros;
 
 pub mod context;
+pub mod controller;
 pub mod extended_capabilities;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod port;
 pub mod registers;
 pub mod ring;
+pub mod usb;
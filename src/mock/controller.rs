@@ -0,0 +1,994 @@
+//! A behavioral model of an xHC.
+//!
+//! [`Controller`] reacts to the values a driver writes to an [`Mmio`] space. Call
+//! [`Controller::step`] whenever the driver may have written a register, and the model processes
+//! the writes as an xHC would.
+//!
+//! # Examples
+//!
+//! ```
+//! use xhci::mock::controller::Controller;
+//! use xhci::mock::Mmio;
+//! use xhci::usb::Speed;
+//!
+//! let mmio = Mmio::new();
+//! let mut xhc = unsafe { Controller::new(mmio.clone()) };
+//! let mut r = unsafe { xhci::Registers::new(mmio.base(), mmio.mapper()) };
+//!
+//! r.operational.usbcmd.update_volatile(|u| {
+//!     u.set_host_controller_reset();
+//! });
+//! xhc.step();
+//! assert!(!r.operational.usbcmd.read_volatile().host_controller_reset());
+//!
+//! xhc.attach(3, Speed::SuperSpeed);
+//!
+//! let p = r.port_register_set.read_volatile_at(2).portsc;
+//! assert!(p.current_connect_status());
+//! assert!(p.connect_status_change());
+//! ```
+
+use super::{Mmio, DOORBELL_OFFSET, OPERATIONAL_OFFSET};
+use crate::ring::trb::command::{AddressDevice, Allowed, ConfigureEndpoint};
+use crate::ring::trb::event::CompletionCode;
+use crate::usb::Speed;
+use alloc::vec::Vec;
+use bit_field::BitField;
+use core::convert::{TryFrom, TryInto};
+use core::ptr;
+
+const USBCMD: usize = OPERATIONAL_OFFSET;
+const USBSTS: usize = OPERATIONAL_OFFSET + 0x04;
+const CRCR: usize = OPERATIONAL_OFFSET + 0x18;
+const DCBAAP: usize = OPERATIONAL_OFFSET + 0x30;
+const PORTSC: usize = OPERATIONAL_OFFSET + 0x400;
+
+const IMAN: usize = 0x20;
+const ERSTSZ: usize = 0x28;
+const ERSTBA: usize = 0x30;
+const ERDP: usize = 0x38;
+
+const USBSTS_RW1C: u32 = 1 << 2 | 1 << 3 | 1 << 4 | 1 << 10;
+const PORTSC_RW: u32 = 1 << 9 | 0b11 << 14 | 0b111 << 25;
+const PORTSC_RW1C: u32 = 0x7f << 17;
+
+/// A reserved bit of the Port Status and Control Register which the model sets whenever it updates
+/// the register.
+///
+/// A driver writes 0 to this bit, so the bit being cleared means the register has been written even
+/// if the other bits are the same as the read value.
+const PORTSC_WRITE_SENTINEL: u32 = 1 << 29;
+
+/// A reserved bit of the USB Status Register and the Interrupter Management Register which the
+/// model sets whenever it updates the registers.
+///
+/// A write built from the default value of the register clears this bit. A read-modify-write which
+/// leaves every bit as read cannot be told apart from no write, so it acknowledges nothing.
+const STATUS_WRITE_SENTINEL: u32 = 1 << 31;
+
+/// The value the model writes to the Doorbell Register 0 after processing the Command Ring.
+///
+/// A driver always writes 0 to the Doorbell Register 0, so a value different from this one means
+/// the doorbell has been rung.
+pub const DOORBELL_IDLE: u32 = 0xffff_ffff;
+
+/// A behavioral model of an xHC.
+///
+/// The model supports the following behaviors.
+///
+/// - The Run/Stop, Host Controller Reset, and `HCHalted` bits.
+/// - The RW1C bits of the USB Status Register, the Port Status and Control Registers, and the
+///   Interrupter Register Set 0. A register is regarded as written when its value changes or when
+///   a reserved bit which the model sets is cleared: bit 29 of the Port Status and Control
+///   Registers, and bit 31 of the USB Status Register and the Interrupter Management Register.
+///   Writing back exactly the read value cannot be told apart from not writing, so acknowledge
+///   the bits of the latter two registers with a value built from the default one.
+/// - Port resets and link state transitions requested by writing to the Port Status and Control
+///   Registers.
+/// - The Command Ring, which is processed when the Doorbell Register 0 is rung. The Enable Slot,
+///   Disable Slot, Address Device, Configure Endpoint, and No Op Commands complete with
+///   [`CompletionCode::Success`] if they are valid. The other Commands complete with
+///   [`CompletionCode::TrbError`].
+/// - The Event Ring of the Interrupter 0, which receives the Command Completion Events and the
+///   Port Status Change Events.
+///
+/// Transfer Rings are not processed. The model supports only the 32-byte Contexts.
+#[derive(Debug)]
+pub struct Controller {
+    mmio: Mmio,
+    running: bool,
+    usbsts: u32,
+    iman: u32,
+    erdp: u32,
+    ports: Vec<Port>,
+    slots: Vec<bool>,
+    command_ring: Option<Dequeue>,
+    event_ring: Option<EventRing>,
+}
+impl Controller {
+    /// Creates a model of an xHC whose registers are in `mmio`.
+    ///
+    /// The Capability Registers and the xHCI Supported Protocol Capabilities of `mmio` must already
+    /// be initialized, as [`Mmio::new`] does. Ports which are not covered by a Supported Protocol
+    /// Capability of the USB 3.x protocol are treated as USB 2.0 ports.
+    ///
+    /// # Safety
+    ///
+    /// The model accesses the memory pointed to by the addresses the driver writes to the
+    /// registers, TRBs, and Contexts, treating the physical addresses as virtual ones. The caller
+    /// must ensure that the addresses are identity-mapped and valid while the model runs.
+    #[must_use]
+    pub unsafe fn new(mmio: Mmio) -> Self {
+        let num_ports = mmio.read_u32(0x04).get_bits(24..=31);
+        let max_slots = mmio.read_u32(0x04).get_bits(0..=7);
+
+        let mut ports: Vec<Port> = (0..num_ports)
+            .map(|i| Port {
+                portsc: mmio.read_u32(PORTSC + usize::try_from(i).unwrap() * 0x10),
+                usb3: false,
+            })
+            .collect();
+
+        for (major, offset, count) in supported_protocols(&mmio) {
+            if major >= 3 {
+                for p in ports.iter_mut().skip(offset - 1).take(count) {
+                    p.usb3 = true;
+                }
+            }
+        }
+
+        let mut s = Self {
+            mmio,
+            running: false,
+            usbsts: 0,
+            iman: 0,
+            erdp: 0,
+            ports,
+            slots: (0..max_slots).map(|_| false).collect(),
+            command_ring: None,
+            event_ring: None,
+        };
+        s.usbsts = s.mmio.read_u32(USBSTS);
+        s.mmio.write_u32(DOORBELL_OFFSET, DOORBELL_IDLE);
+        s.flush();
+        s
+    }
+
+    /// Returns the number of the Root Hub Ports.
+    #[must_use]
+    pub fn number_of_ports(&self) -> u8 {
+        u8::try_from(self.ports.len()).unwrap()
+    }
+
+    /// Returns `true` if the Device Slot `slot_id` is enabled.
+    #[must_use]
+    pub fn slot_enabled(&self, slot_id: u8) -> bool {
+        usize::from(slot_id)
+            .checked_sub(1)
+            .and_then(|i| self.slots.get(i))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Connects a device of `speed` to the Root Hub Port `port`.
+    ///
+    /// A USB 3.x port is enabled immediately. A USB 2.0 port is enabled after the driver resets
+    /// it. A Port Status Change Event is posted if the model is running.
+    ///
+    /// The values written to the registers are processed before connecting the device.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `port` is not a valid port number, or if `speed` is not supported by
+    /// the port.
+    pub fn attach(&mut self, port: u8, speed: Speed) {
+        self.step();
+
+        let p = self.port_mut(port);
+        assert_eq!(
+            p.usb3,
+            speed.is_super_speed(),
+            "The speed is not supported by the port."
+        );
+
+        p.portsc.set_bit(CCS, true);
+        p.portsc.set_bit(CSC, true);
+        p.portsc.set_bits(SPEED, speed_id(speed));
+        if p.usb3 {
+            p.portsc.set_bit(PED, true);
+            p.portsc.set_bits(PLS, U0);
+        } else {
+            p.portsc.set_bits(PLS, POLLING);
+        }
+
+        self.port_status_changed(port);
+        self.flush();
+    }
+
+    /// Disconnects the device connected to the Root Hub Port `port`.
+    ///
+    /// A Port Status Change Event is posted if the model is running.
+    ///
+    /// The values written to the registers are processed before disconnecting the device.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `port` is not a valid port number.
+    pub fn detach(&mut self, port: u8) {
+        self.step();
+
+        let p = self.port_mut(port);
+        p.portsc.set_bit(CCS, false);
+        p.portsc.set_bit(PED, false);
+        p.portsc.set_bit(CSC, true);
+        p.portsc.set_bits(SPEED, 0);
+        p.portsc.set_bits(PLS, RX_DETECT);
+
+        self.port_status_changed(port);
+        self.flush();
+    }
+
+    /// Processes the values written to the registers since the last call.
+    ///
+    /// Port resets complete and the rung Command Ring is processed within a single call.
+    pub fn step(&mut self) {
+        let usbcmd = self.mmio.read_u32(USBCMD);
+        if usbcmd.get_bit(1) {
+            self.reset();
+            return;
+        }
+
+        self.sync_usbsts();
+        self.sync_interrupter();
+
+        if usbcmd.get_bit(0) && !self.running {
+            self.start();
+        } else if !usbcmd.get_bit(0) && self.running {
+            self.stop();
+        }
+
+        for i in 0..self.ports.len() {
+            self.sync_port(i);
+        }
+
+        if self.mmio.read_u32(DOORBELL_OFFSET) != DOORBELL_IDLE {
+            self.mmio.write_u32(DOORBELL_OFFSET, DOORBELL_IDLE);
+
+            if self.running {
+                self.process_command_ring();
+            }
+        }
+
+        self.flush();
+    }
+
+    fn reset(&mut self) {
+        for o in (OPERATIONAL_OFFSET..PORTSC).step_by(4) {
+            self.mmio.write_u32(o, 0);
+        }
+        // PAGESIZE: 4KB.
+        self.mmio.write_u32(OPERATIONAL_OFFSET + 0x08, 1);
+
+        let rt = self.runtime_offset();
+        for o in (rt..rt + 0x40).step_by(4) {
+            self.mmio.write_u32(o, 0);
+        }
+
+        for p in &mut self.ports {
+            let connected = p.portsc.get_bit(CCS);
+            let speed = p.portsc.get_bits(SPEED);
+
+            p.portsc = 1 << PP;
+            if !connected {
+                p.portsc.set_bits(PLS, RX_DETECT);
+            } else if p.usb3 {
+                p.portsc |= 1 << CCS | 1 << PED | 1 << CSC;
+                p.portsc.set_bits(SPEED, speed);
+            } else {
+                p.portsc |= 1 << CCS | 1 << CSC;
+                p.portsc.set_bits(SPEED, speed);
+                p.portsc.set_bits(PLS, POLLING);
+            }
+        }
+
+        self.slots.iter_mut().for_each(|s| *s = false);
+        self.running = false;
+        self.usbsts = 1;
+        self.iman = 0;
+        self.erdp = 0;
+        self.command_ring = None;
+        self.event_ring = None;
+
+        self.mmio.write_u32(DOORBELL_OFFSET, DOORBELL_IDLE);
+        self.flush();
+    }
+
+    fn start(&mut self) {
+        self.running = true;
+        self.usbsts.set_bit(0, false);
+
+        let rt = self.runtime_offset();
+        let size = self.mmio.read_u32(rt + ERSTSZ).get_bits(0..=15);
+        self.event_ring = (size != 0).then(|| EventRing {
+            table: self.mmio.read_u64(rt + ERSTBA) & !0x3f,
+            size,
+            segment: 0,
+            index: 0,
+            cycle_state: true,
+        });
+
+        for i in 0..self.ports.len() {
+            if self.ports[i].portsc & PORTSC_RW1C != 0 {
+                self.post_port_status_change(i);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.running = false;
+        self.usbsts.set_bit(0, true);
+
+        let crcr = self.mmio.read_u32(CRCR);
+        self.mmio.write_u32(CRCR, crcr & !(1 << 3));
+    }
+
+    fn sync_usbsts(&mut self) {
+        let w = self.mmio.read_u32(USBSTS);
+        if w != self.usbsts | STATUS_WRITE_SENTINEL {
+            self.usbsts &= !(w & USBSTS_RW1C);
+        }
+    }
+
+    fn sync_interrupter(&mut self) {
+        let rt = self.runtime_offset();
+
+        let w = self.mmio.read_u32(rt + IMAN);
+        if w != self.iman | STATUS_WRITE_SENTINEL {
+            let pending = self.iman.get_bit(0) && !w.get_bit(0);
+            self.iman = (w & 0b10) | u32::from(pending);
+        }
+
+        let w = self.mmio.read_u32(rt + ERDP);
+        if w != self.erdp {
+            let busy = self.erdp.get_bit(3) && !w.get_bit(3);
+            self.erdp = (w & !(1 << 3)) | u32::from(busy) << 3;
+        }
+    }
+
+    fn sync_port(&mut self, i: usize) {
+        let w = self.mmio.read_u32(PORTSC + i * 0x10);
+        let p = &mut self.ports[i];
+        if w == p.portsc | PORTSC_WRITE_SENTINEL {
+            return;
+        }
+        let w = w & !PORTSC_WRITE_SENTINEL;
+
+        let old = p.portsc;
+        let mut v = (old & !PORTSC_RW) | (w & PORTSC_RW);
+        v &= !(w & PORTSC_RW1C);
+
+        if w.get_bit(PED) {
+            v.set_bit(PED, false);
+        }
+
+        let mut changed = false;
+
+        if w.get_bit(LWS) {
+            let from = old.get_bits(PLS);
+            let to = w.get_bits(PLS);
+            v.set_bits(PLS, to);
+
+            if from == U3 && to == U0 {
+                v.set_bit(PLC, true);
+                changed = true;
+            }
+        }
+
+        let warm = p.usb3 && w.get_bit(WPR);
+        if (w.get_bit(PR) || warm) && v.get_bit(CCS) {
+            v.set_bit(PED, true);
+            v.set_bit(PRC, true);
+            v.set_bit(WRC, warm);
+            v.set_bits(PLS, U0);
+            changed = true;
+        }
+
+        p.portsc = v;
+        if changed {
+            self.post_port_status_change(i);
+        }
+    }
+
+    fn process_command_ring(&mut self) {
+        let mut ring = self.command_ring.unwrap_or_else(|| {
+            let crcr = self.mmio.read_u64(CRCR);
+            Dequeue {
+                pointer: crcr & !0x3f,
+                cycle_state: crcr.get_bit(0),
+            }
+        });
+
+        let crcr = self.mmio.read_u32(CRCR);
+        self.mmio.write_u32(CRCR, crcr | 1 << 3);
+
+        loop {
+            // SAFETY: The caller of `Controller::new` ensures that the address is valid.
+            let raw = unsafe { read_trb(ring.pointer) };
+            if raw[3].get_bit(0) != ring.cycle_state {
+                break;
+            }
+
+            let result = match Allowed::try_from(raw) {
+                Ok(Allowed::Link(l)) => {
+                    ring.pointer = l.ring_segment_pointer();
+                    if l.toggle_cycle() {
+                        ring.cycle_state = !ring.cycle_state;
+                    }
+                    continue;
+                }
+                Ok(Allowed::EnableSlot(_)) => self.enable_slot(),
+                Ok(Allowed::DisableSlot(d)) => (self.disable_slot(d.slot_id()), d.slot_id()),
+                Ok(Allowed::AddressDevice(a)) => (self.address_device(&a), a.slot_id()),
+                Ok(Allowed::ConfigureEndpoint(c)) => (self.configure_endpoint(&c), c.slot_id()),
+                Ok(Allowed::Noop(_)) => (CompletionCode::Success, 0),
+                _ => (CompletionCode::TrbError, 0),
+            };
+
+            self.post_command_completion(ring.pointer, result);
+            ring.pointer += 16;
+        }
+
+        self.command_ring = Some(ring);
+    }
+
+    fn enable_slot(&mut self) -> (CompletionCode, u8) {
+        match self.slots.iter().position(|s| !s) {
+            Some(i) => {
+                self.slots[i] = true;
+                (CompletionCode::Success, u8::try_from(i + 1).unwrap())
+            }
+            None => (CompletionCode::NoSlotsAvailableError, 0),
+        }
+    }
+
+    fn disable_slot(&mut self, slot_id: u8) -> CompletionCode {
+        if !self.slot_enabled(slot_id) {
+            return CompletionCode::SlotNotEnabledError;
+        }
+
+        self.slots[usize::from(slot_id) - 1] = false;
+        CompletionCode::Success
+    }
+
+    fn address_device(&mut self, a: &AddressDevice) -> CompletionCode {
+        let Some(output) = self.output_context(a.slot_id()) else {
+            return CompletionCode::SlotNotEnabledError;
+        };
+        let input = a.input_context_pointer();
+
+        // SAFETY: The caller of `Controller::new` ensures that the addresses are valid.
+        unsafe {
+            copy_context(output, input + CONTEXT_SIZE);
+            copy_context(output + CONTEXT_SIZE, input + 2 * CONTEXT_SIZE);
+
+            let mut slot = read_dword(output + 12);
+            if a.block_set_address_request() {
+                slot.set_bits(27..=31, SLOT_DEFAULT);
+                slot.set_bits(0..=7, 0);
+            } else {
+                slot.set_bits(27..=31, SLOT_ADDRESSED);
+                slot.set_bits(0..=7, a.slot_id().into());
+            }
+            write_dword(output + 12, slot);
+
+            set_endpoint_state(output + CONTEXT_SIZE, ENDPOINT_RUNNING);
+        }
+
+        CompletionCode::Success
+    }
+
+    fn configure_endpoint(&mut self, c: &ConfigureEndpoint) -> CompletionCode {
+        let Some(output) = self.output_context(c.slot_id()) else {
+            return CompletionCode::SlotNotEnabledError;
+        };
+        let input = c.input_context_pointer();
+
+        // SAFETY: The caller of `Controller::new` ensures that the addresses are valid.
+        unsafe {
+            let drop = read_dword(input);
+            let add = read_dword(input + 4);
+            let deconfigure = c.deconfigure();
+
+            if !deconfigure && add.get_bit(0) {
+                let address = read_dword(output + 12).get_bits(0..=7);
+                copy_context(output, input + CONTEXT_SIZE);
+
+                let mut slot = read_dword(output + 12);
+                slot.set_bits(0..=7, address);
+                write_dword(output + 12, slot);
+            }
+
+            for dci in 2..32 {
+                let o = output + CONTEXT_SIZE * dci;
+                let i = input + CONTEXT_SIZE * (dci + 1);
+                let dci = usize::try_from(dci).unwrap();
+
+                if deconfigure || drop.get_bit(dci) {
+                    set_endpoint_state(o, ENDPOINT_DISABLED);
+                }
+                if !deconfigure && add.get_bit(dci) {
+                    copy_context(o, i);
+                    set_endpoint_state(o, ENDPOINT_RUNNING);
+                }
+            }
+
+            let mut slot = read_dword(output + 12);
+            slot.set_bits(
+                27..=31,
+                if deconfigure {
+                    SLOT_ADDRESSED
+                } else {
+                    SLOT_CONFIGURED
+                },
+            );
+            write_dword(output + 12, slot);
+        }
+
+        CompletionCode::Success
+    }
+
+    fn output_context(&self, slot_id: u8) -> Option<u64> {
+        if !self.slot_enabled(slot_id) {
+            return None;
+        }
+
+        let dcbaa = self.mmio.read_u64(DCBAAP) & !0x3f;
+
+        // SAFETY: The caller of `Controller::new` ensures that the address is valid.
+        let l = unsafe { read_dword(dcbaa + u64::from(slot_id) * 8) };
+        // SAFETY: Same as above.
+        let u = unsafe { read_dword(dcbaa + u64::from(slot_id) * 8 + 4) };
+
+        Some((u64::from(u) << 32 | u64::from(l)) & !0x3f)
+    }
+
+    fn port_status_changed(&mut self, port: u8) {
+        if self.running {
+            self.post_port_status_change(usize::from(port) - 1);
+        }
+    }
+
+    fn post_port_status_change(&mut self, i: usize) {
+        self.usbsts.set_bit(4, true);
+
+        let port_id = u32::try_from(i + 1).unwrap();
+        self.post_event([port_id << 24, 0, 1 << 24, 34 << 10]);
+    }
+
+    fn post_command_completion(&mut self, trb: u64, (code, slot_id): (CompletionCode, u8)) {
+        self.post_event([
+            trb.get_bits(0..32).try_into().unwrap(),
+            trb.get_bits(32..64).try_into().unwrap(),
+            (code as u32) << 24,
+            u32::from(slot_id) << 24 | 33 << 10,
+        ]);
+    }
+
+    fn post_event(&mut self, mut trb: [u32; 4]) {
+        let Some(ring) = &mut self.event_ring else {
+            return;
+        };
+
+        let entry = ring.table + u64::from(ring.segment) * 16;
+
+        // SAFETY: The caller of `Controller::new` ensures that the addresses are valid.
+        unsafe {
+            let base = u64::from(read_dword(entry + 4)) << 32 | u64::from(read_dword(entry));
+            let size = read_dword(entry + 8).get_bits(0..=15);
+
+            trb[3].set_bit(0, ring.cycle_state);
+            write_trb(base + u64::from(ring.index) * 16, trb);
+
+            ring.index += 1;
+            if ring.index >= size {
+                ring.index = 0;
+                ring.segment += 1;
+
+                if ring.segment == ring.size {
+                    ring.segment = 0;
+                    ring.cycle_state = !ring.cycle_state;
+                }
+            }
+        }
+
+        self.usbsts.set_bit(3, true);
+        self.erdp.set_bit(3, true);
+        if self.iman.get_bit(1) {
+            self.iman.set_bit(0, true);
+        }
+    }
+
+    fn flush(&mut self) {
+        self.mmio
+            .write_u32(USBSTS, self.usbsts | STATUS_WRITE_SENTINEL);
+
+        let rt = self.runtime_offset();
+        self.mmio
+            .write_u32(rt + IMAN, self.iman | STATUS_WRITE_SENTINEL);
+        self.mmio.write_u32(rt + ERDP, self.erdp);
+
+        for (i, p) in self.ports.iter().enumerate() {
+            self.mmio
+                .write_u32(PORTSC + i * 0x10, p.portsc | PORTSC_WRITE_SENTINEL);
+        }
+
+        let usbcmd = self.mmio.read_u32(USBCMD);
+        self.mmio.write_u32(USBCMD, usbcmd & !(1 << 1));
+    }
+
+    fn port_mut(&mut self, port: u8) -> &mut Port {
+        usize::from(port)
+            .checked_sub(1)
+            .and_then(|i| self.ports.get_mut(i))
+            .expect("Invalid port number.")
+    }
+
+    fn runtime_offset(&self) -> usize {
+        usize::try_from(self.mmio.read_u32(0x18) & !0x1f).unwrap()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Port {
+    portsc: u32,
+    usb3: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Dequeue {
+    pointer: u64,
+    cycle_state: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct EventRing {
+    table: u64,
+    size: u32,
+    segment: u32,
+    index: u32,
+    cycle_state: bool,
+}
+
+const CCS: usize = 0;
+const PED: usize = 1;
+const PR: usize = 4;
+const PLS: core::ops::RangeInclusive<usize> = 5..=8;
+const PP: usize = 9;
+const SPEED: core::ops::RangeInclusive<usize> = 10..=13;
+const LWS: usize = 16;
+const CSC: usize = 17;
+const WRC: usize = 19;
+const PRC: usize = 21;
+const PLC: usize = 22;
+const WPR: usize = 31;
+
+const U0: u32 = 0;
+const U3: u32 = 3;
+const RX_DETECT: u32 = 5;
+const POLLING: u32 = 7;
+
+const CONTEXT_SIZE: u64 = 32;
+
+const SLOT_DEFAULT: u32 = 1;
+const SLOT_ADDRESSED: u32 = 2;
+const SLOT_CONFIGURED: u32 = 3;
+
+const ENDPOINT_DISABLED: u32 = 0;
+const ENDPOINT_RUNNING: u32 = 1;
+
+fn speed_id(speed: Speed) -> u32 {
+    match speed {
+        Speed::Full => 1,
+        Speed::Low => 2,
+        Speed::High => 3,
+        Speed::SuperSpeed => 4,
+        Speed::SuperSpeedPlus => 5,
+    }
+}
+
+/// Returns the Major Revision, the Compatible Port Offset, and the Compatible Port Count of each
+/// xHCI Supported Protocol Capability.
+fn supported_protocols(mmio: &Mmio) -> Vec<(u32, usize, usize)> {
+    let mut v = Vec::new();
+
+    let xecp = mmio.read_u32(0x10).get_bits(16..=31);
+    if xecp == 0 {
+        return v;
+    }
+
+    let mut o = usize::try_from(xecp).unwrap() << 2;
+    loop {
+        let h = mmio.read_u32(o);
+        if h.get_bits(0..=7) == 2 {
+            let ports = mmio.read_u32(o + 8);
+            v.push((
+                h.get_bits(24..=31),
+                usize::try_from(ports.get_bits(0..=7)).unwrap(),
+                usize::try_from(ports.get_bits(8..=15)).unwrap(),
+            ));
+        }
+
+        let next = usize::try_from(h.get_bits(8..=15)).unwrap();
+        if next == 0 {
+            return v;
+        }
+        o += next << 2;
+    }
+}
+
+unsafe fn read_dword(addr: u64) -> u32 {
+    ptr::read_volatile(usize::try_from(addr).unwrap() as *const u32)
+}
+
+unsafe fn write_dword(addr: u64, v: u32) {
+    ptr::write_volatile(usize::try_from(addr).unwrap() as *mut u32, v);
+}
+
+unsafe fn read_trb(addr: u64) -> [u32; 4] {
+    let mut t = [0; 4];
+    for (i, d) in (0..).step_by(4).zip(t.iter_mut()) {
+        *d = read_dword(addr + i);
+    }
+    t
+}
+
+/// Writes a TRB. The dword containing the Cycle bit is written last.
+unsafe fn write_trb(addr: u64, trb: [u32; 4]) {
+    for (i, d) in (0..).step_by(4).zip(trb).take(3) {
+        write_dword(addr + i, d);
+    }
+    write_dword(addr + 12, trb[3]);
+}
+
+unsafe fn copy_context(dst: u64, src: u64) {
+    for i in (0..CONTEXT_SIZE).step_by(4) {
+        write_dword(dst + i, read_dword(src + i));
+    }
+}
+
+unsafe fn set_endpoint_state(addr: u64, state: u32) {
+    let mut d = read_dword(addr);
+    d.set_bits(0..=2, state);
+    write_dword(addr, d);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::Mapper;
+    use crate::registers::operational::{PortChange, PortRegisterSet, UsbStatusRegister};
+    use crate::registers::runtime::InterrupterManagementRegister;
+    use crate::registers::Doorbell;
+    use crate::ring::trb::command::{EnableSlot, Noop};
+    use crate::ring::trb::event::Allowed as Event;
+    use crate::ring::{command, event, Segment};
+    use crate::Registers;
+    use core::slice;
+
+    #[test]
+    fn initialize_and_enumerate() {
+        let mmio = Mmio::new();
+        let memory = Mmio::zeroed(0x5000);
+        let base = u64::try_from(memory.base()).unwrap();
+
+        // SAFETY: `memory` is identity-mapped.
+        let mut xhc = unsafe { Controller::new(mmio.clone()) };
+        let mut r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+
+        let trbs = |offset: usize| {
+            // SAFETY: The TRBs are inside `memory`, and the ranges do not overlap.
+            unsafe { slice::from_raw_parts_mut((memory.base() + offset) as *mut [u32; 4], 16) }
+        };
+        let mut command_ring = command::Ring::new(Segment::new(trbs(0x1000), base + 0x1000));
+        let mut event_segments = [Segment::new(trbs(0x2000), base + 0x2000)];
+        let mut event_ring = event::Ring::new(&mut event_segments);
+        // SAFETY: The table is inside `memory`, and it does not overlap with the TRBs.
+        let table = unsafe {
+            slice::from_raw_parts_mut((memory.base() + 0x3000) as *mut event::SegmentTableEntry, 1)
+        };
+        event_ring.write_segment_table(table);
+
+        r.operational.usbcmd.update_volatile(|u| {
+            u.set_host_controller_reset();
+        });
+        xhc.step();
+        assert!(!r.operational.usbcmd.read_volatile().host_controller_reset());
+        assert!(r.operational.usbsts.read_volatile().hc_halted());
+
+        r.operational.dcbaap.update_volatile(|d| d.set(base));
+        r.operational.crcr.update_volatile(|c| {
+            command_ring.initialize_crcr(c);
+        });
+        let mut i = r.interrupter_register_set.interrupter_mut(0);
+        i.erstsz.update_volatile(|s| s.set(1));
+        i.erstba.update_volatile(|a| a.set(base + 0x3000));
+        i.erdp.update_volatile(|e| event_ring.update_erdp(e));
+        i.iman.update_volatile(|m| {
+            m.set_interrupt_enable();
+        });
+        r.operational.usbcmd.update_volatile(|u| {
+            u.set_run_stop();
+        });
+        xhc.step();
+        assert!(!r.operational.usbsts.read_volatile().hc_halted());
+
+        let noop = command_ring.enqueue(Noop::new().into()).unwrap();
+        let enable_slot = command_ring.enqueue(EnableSlot::new().into()).unwrap();
+        r.doorbell.write_volatile_at(0, Doorbell::default());
+        xhc.step();
+
+        assert!(r.operational.usbsts.read_volatile().event_interrupt());
+        assert!(r
+            .interrupter_register_set
+            .interrupter(0)
+            .iman
+            .read_volatile()
+            .interrupt_pending());
+
+        for (addr, slot_id) in [(noop, 0), (enable_slot, 1)] {
+            let Some(Ok(Event::CommandCompletion(c))) = event_ring.next() else {
+                panic!("No Command Completion Event.");
+            };
+            assert_eq!(c.command_trb_pointer(), addr);
+            assert_eq!(c.completion_code(), Ok(CompletionCode::Success));
+            assert_eq!(c.slot_id(), slot_id);
+            command_ring.handle_completion(&c);
+        }
+        assert!(event_ring.next().is_none());
+        assert!(xhc.slot_enabled(1));
+
+        xhc.attach(1, Speed::High);
+
+        let Some(Ok(Event::PortStatusChange(p))) = event_ring.next() else {
+            panic!("No Port Status Change Event.");
+        };
+        assert_eq!(p.port_id(), 1);
+
+        r.port_register_set.update_volatile_at(0, |p| {
+            p.portsc.set_port_reset();
+        });
+        xhc.step();
+
+        let p = r.port_register_set.read_volatile_at(0).portsc;
+        assert!(p.port_enabled_disabled());
+        assert!(p.port_reset_change());
+        assert_eq!(p.port_speed(), 3);
+        assert!(event_ring.next().is_some());
+    }
+
+    /// Starts the model with an Event Ring in `memory`, and connects a device to the port 1.
+    fn start_and_attach(mmio: &Mmio, memory: &Mmio) -> (Controller, Registers<Mapper>) {
+        let base = u64::try_from(memory.base()).unwrap();
+
+        // SAFETY: `memory` is identity-mapped.
+        let mut xhc = unsafe { Controller::new(mmio.clone()) };
+        let mut r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+
+        // SAFETY: The TRBs and the table are inside `memory`, and they do not overlap.
+        let (trbs, table) = unsafe {
+            (
+                slice::from_raw_parts_mut((memory.base() + 0x1000) as *mut [u32; 4], 16),
+                slice::from_raw_parts_mut(memory.base() as *mut event::SegmentTableEntry, 1),
+            )
+        };
+        let mut event_segments = [Segment::new(trbs, base + 0x1000)];
+        let event_ring = event::Ring::new(&mut event_segments);
+        event_ring.write_segment_table(table);
+
+        let mut i = r.interrupter_register_set.interrupter_mut(0);
+        i.erstsz.update_volatile(|s| s.set(1));
+        i.erstba.update_volatile(|a| a.set(base));
+        i.erdp.update_volatile(|e| event_ring.update_erdp(e));
+        i.iman.update_volatile(|m| {
+            m.set_interrupt_enable();
+        });
+        r.operational.usbcmd.update_volatile(|u| {
+            u.set_run_stop();
+        });
+        xhc.step();
+
+        xhc.attach(1, Speed::High);
+
+        (xhc, r)
+    }
+
+    fn interrupt_pending(r: &Registers<Mapper>) -> bool {
+        r.interrupter_register_set
+            .interrupter(0)
+            .iman
+            .read_volatile()
+            .interrupt_pending()
+    }
+
+    #[test]
+    fn unacknowledged_bits_stay_set() {
+        let (mmio, memory) = (Mmio::new(), Mmio::zeroed(0x2000));
+        let (mut xhc, mut r) = start_and_attach(&mmio, &memory);
+
+        xhc.step();
+
+        // Writing back the read value is not regarded as an acknowledgement.
+        r.operational.usbsts.update_volatile(|_| {});
+        r.interrupter_register_set
+            .interrupter_mut(0)
+            .iman
+            .update_volatile(|_| {});
+        xhc.step();
+
+        let usbsts = r.operational.usbsts.read_volatile();
+        assert!(usbsts.event_interrupt() && usbsts.port_change_detect());
+        assert!(interrupt_pending(&r));
+    }
+
+    #[test]
+    fn acknowledge_change_bits() {
+        let (mmio, memory) = (Mmio::new(), Mmio::zeroed(0x2000));
+        let (mut xhc, mut r) = start_and_attach(&mmio, &memory);
+
+        let usbsts = r.operational.usbsts.read_volatile();
+        assert!(usbsts.event_interrupt() && usbsts.port_change_detect());
+        assert!(interrupt_pending(&r));
+
+        // Writing 0 to a RW1C bit keeps it, and the Port Status and Control Register keeps its
+        // change bits until it is written.
+        r.operational.usbsts.update_volatile(|s| {
+            s.set_0_event_interrupt().clear_port_change_detect();
+        });
+        r.interrupter_register_set
+            .interrupter_mut(0)
+            .iman
+            .update_volatile(|m| {
+                m.set_0_interrupt_pending();
+            });
+        xhc.step();
+
+        let usbsts = r.operational.usbsts.read_volatile();
+        assert!(usbsts.event_interrupt() && !usbsts.port_change_detect());
+        assert!(interrupt_pending(&r));
+        assert!(r
+            .port_register_set
+            .read_volatile_at(0)
+            .portsc
+            .connect_status_change());
+
+        PortRegisterSet::update_portsc_volatile_at(&mut r.port_register_set, 0, |_, w| {
+            w.acknowledge(PortChange::ConnectStatus);
+        });
+
+        let mut usbsts = UsbStatusRegister::default();
+        usbsts.clear_event_interrupt();
+        r.operational.usbsts.write_volatile(usbsts);
+
+        let mut iman = InterrupterManagementRegister::default();
+        iman.set_interrupt_enable().clear_interrupt_pending();
+        r.interrupter_register_set
+            .interrupter_mut(0)
+            .iman
+            .write_volatile(iman);
+        xhc.step();
+
+        let p = r.port_register_set.read_volatile_at(0).portsc;
+        assert!(p.current_connect_status());
+        assert!(!p.connect_status_change());
+
+        assert!(!r.operational.usbsts.read_volatile().event_interrupt());
+
+        let iman = r
+            .interrupter_register_set
+            .interrupter(0)
+            .iman
+            .read_volatile();
+        assert!(!iman.interrupt_pending());
+        assert!(iman.interrupt_enable());
+    }
+}
@@ -0,0 +1,344 @@
+//! An in-memory MMIO space for tests.
+//!
+//! This module is available with the `mock` feature. It allocates memory from the heap, so the
+//! `alloc` crate is required.
+//!
+//! The addresses of [`Mmio`] are identity-mapped: the "physical" address passed to
+//! [`Registers::new`](crate::Registers::new) is the virtual address of the heap region.
+//!
+//! # Examples
+//!
+//! ```
+//! use xhci::mock::Mmio;
+//!
+//! let mmio = Mmio::new();
+//! let r = unsafe { xhci::Registers::new(mmio.base(), mmio.mapper()) };
+//!
+//! assert_eq!(r.capability.hcsparams1.read_volatile().number_of_ports(), 4);
+//! assert!(r.operational.usbsts.read_volatile().hc_halted());
+//! ```
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use alloc::rc::Rc;
+use core::num::NonZeroUsize;
+use core::ptr::{self, NonNull};
+
+pub mod controller;
+
+/// The offset of the Host Controller Operational Registers, which is the value of CAPLENGTH.
+pub const OPERATIONAL_OFFSET: usize = 0x20;
+/// The offset of the xHCI Extended Capabilities.
+pub const EXTENDED_CAPABILITIES_OFFSET: usize = 0x1000;
+/// The offset of the Doorbell Array, which is the value of DBOFF.
+pub const DOORBELL_OFFSET: usize = 0x2000;
+/// The offset of the Runtime Registers, which is the value of RTSOFF.
+pub const RUNTIME_OFFSET: usize = 0x3000;
+/// The size of the MMIO space created by [`Mmio::new`].
+pub const DEFAULT_SIZE: usize = 0x4000;
+
+/// The number of Device Slots of the default layout.
+pub const NUM_SLOTS: u8 = 8;
+/// The number of Interrupters of the default layout.
+pub const NUM_INTERRUPTERS: u16 = 4;
+/// The number of Root Hub Ports of the default layout. Ports 1 and 2 are USB 2.0 ports, and
+/// ports 3 and 4 are USB 3.1 ports.
+pub const NUM_PORTS: u8 = 4;
+/// The number of Scratchpad Buffers the default layout requires.
+pub const NUM_SCRATCHPAD_BUFFERS: u32 = 2;
+
+/// An MMIO space backed by the heap.
+///
+/// Cloning this struct does not copy the region. All the clones share the same memory.
+#[derive(Clone, Debug)]
+pub struct Mmio {
+    region: Rc<Region>,
+}
+impl Mmio {
+    /// Creates an MMIO space of [`DEFAULT_SIZE`] bytes with a plausible register layout.
+    ///
+    /// The layout has the Capability Registers at offset 0, the Operational Registers at
+    /// [`OPERATIONAL_OFFSET`], the xHCI Extended Capabilities at [`EXTENDED_CAPABILITIES_OFFSET`],
+    /// the Doorbell Array at [`DOORBELL_OFFSET`], and the Runtime Registers at [`RUNTIME_OFFSET`].
+    ///
+    /// The Extended Capabilities are a USB Legacy Support Capability, and two xHCI Supported
+    /// Protocol Capabilities for USB 2.0 and USB 3.1. The controller is halted, and all the ports
+    /// are powered without any device connected.
+    #[must_use]
+    pub fn new() -> Self {
+        let m = Self::zeroed(DEFAULT_SIZE);
+
+        // CAPLENGTH and HCIVERSION 1.1.
+        m.write_u32(
+            0x00,
+            0x0110_0000 | u32::try_from(OPERATIONAL_OFFSET).unwrap(),
+        );
+        // HCSPARAMS1
+        m.write_u32(
+            0x04,
+            u32::from(NUM_PORTS) << 24 | u32::from(NUM_INTERRUPTERS) << 8 | u32::from(NUM_SLOTS),
+        );
+        // HCSPARAMS2: ERST Max = 4, and Max Scratchpad Buffers.
+        m.write_u32(0x08, NUM_SCRATCHPAD_BUFFERS << 27 | 4 << 4);
+        // HCCPARAMS1: xECP, and 64-bit Addressing Capability.
+        m.write_u32(
+            0x10,
+            u32::try_from(EXTENDED_CAPABILITIES_OFFSET >> 2).unwrap() << 16 | 1,
+        );
+        m.write_u32(0x14, u32::try_from(DOORBELL_OFFSET).unwrap());
+        m.write_u32(0x18, u32::try_from(RUNTIME_OFFSET).unwrap());
+
+        // USBSTS: HCHalted.
+        m.write_u32(OPERATIONAL_OFFSET + 0x04, 1);
+        // PAGESIZE: 4KB.
+        m.write_u32(OPERATIONAL_OFFSET + 0x08, 1);
+
+        for i in 0..usize::from(NUM_PORTS) {
+            // PORTSC: Port Power.
+            m.write_u32(OPERATIONAL_OFFSET + 0x400 + i * 0x10, 1 << 9);
+        }
+
+        let x = EXTENDED_CAPABILITIES_OFFSET;
+
+        // USB Legacy Support Capability.
+        m.write_u32(x, 4 << 8 | 1);
+
+        // xHCI Supported Protocol Capability for USB 2.0, ports 1 and 2.
+        m.write_u32(x + 0x10, 0x0200_0000 | 4 << 8 | 2);
+        m.write_u32(x + 0x14, u32::from_le_bytes(*b"USB "));
+        m.write_u32(x + 0x18, 2 << 8 | 1);
+
+        // xHCI Supported Protocol Capability for USB 3.1, ports 3 and 4.
+        m.write_u32(x + 0x20, 0x0310_0000 | 2);
+        m.write_u32(x + 0x24, u32::from_le_bytes(*b"USB "));
+        m.write_u32(x + 0x28, 2 << 8 | 3);
+
+        m
+    }
+
+    /// Creates an MMIO space of `bytes` bytes which is filled with 0.
+    ///
+    /// The region is 4096-byte aligned.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `bytes` is 0.
+    #[must_use]
+    pub fn zeroed(bytes: usize) -> Self {
+        assert_ne!(bytes, 0, "The MMIO space must not be empty.");
+
+        let layout = Layout::from_size_align(bytes, 4096).unwrap();
+
+        // SAFETY: The size of `layout` is not 0.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout));
+
+        Self {
+            region: Rc::new(Region { ptr, layout }),
+        }
+    }
+
+    /// Returns the base address of the MMIO space.
+    #[must_use]
+    pub fn base(&self) -> usize {
+        self.region.ptr.as_ptr() as usize
+    }
+
+    /// Returns the size of the MMIO space in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.region.layout.size()
+    }
+
+    /// Returns `false`. The MMIO space is never empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a [`Mapper`] to access this MMIO space.
+    #[must_use]
+    pub fn mapper(&self) -> Mapper {
+        Mapper {
+            region: Rc::clone(&self.region),
+        }
+    }
+
+    /// Reads the dword at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `offset` is not 4-byte aligned, or if the dword is out of range.
+    #[must_use]
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        let p = self.region.pointer_to::<u32>(offset);
+
+        // SAFETY: `p` points to the inside of the region and is aligned.
+        unsafe { ptr::read_volatile(p) }
+    }
+
+    /// Writes `v` to the dword at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `offset` is not 4-byte aligned, or if the dword is out of range.
+    pub fn write_u32(&self, offset: usize, v: u32) {
+        let p = self.region.pointer_to::<u32>(offset);
+
+        // SAFETY: `p` points to the inside of the region and is aligned.
+        unsafe { ptr::write_volatile(p, v) };
+    }
+
+    /// Reads the qword at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `offset` is not 8-byte aligned, or if the qword is out of range.
+    #[must_use]
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        let p = self.region.pointer_to::<u64>(offset);
+
+        // SAFETY: `p` points to the inside of the region and is aligned.
+        unsafe { ptr::read_volatile(p) }
+    }
+
+    /// Writes `v` to the qword at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `offset` is not 8-byte aligned, or if the qword is out of range.
+    pub fn write_u64(&self, offset: usize, v: u64) {
+        let p = self.region.pointer_to::<u64>(offset);
+
+        // SAFETY: `p` points to the inside of the region and is aligned.
+        unsafe { ptr::write_volatile(p, v) };
+    }
+}
+impl Default for Mmio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An implementation of [`accessor::Mapper`] for [`Mmio`].
+///
+/// The mapping is the identity mapping. [`accessor::Mapper::map`] panics if the requested range is
+/// not inside the MMIO space.
+#[derive(Clone, Debug)]
+pub struct Mapper {
+    region: Rc<Region>,
+}
+impl accessor::Mapper for Mapper {
+    unsafe fn map(&mut self, phys_start: usize, bytes: usize) -> NonZeroUsize {
+        let base = self.region.ptr.as_ptr() as usize;
+
+        assert!(
+            phys_start >= base && phys_start + bytes <= base + self.region.layout.size(),
+            "The range {:#x}..{:#x} is outside of the MMIO space.",
+            phys_start,
+            phys_start + bytes
+        );
+
+        NonZeroUsize::new(phys_start).unwrap()
+    }
+
+    fn unmap(&mut self, _virt_start: usize, _bytes: usize) {}
+}
+
+#[derive(Debug)]
+struct Region {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+impl Region {
+    fn pointer_to<T>(&self, offset: usize) -> *mut T {
+        assert_eq!(offset % align_of::<T>(), 0, "The offset is not aligned.");
+        assert!(
+            offset + size_of::<T>() <= self.layout.size(),
+            "The offset is out of range."
+        );
+
+        // SAFETY: The offset is inside the region.
+        unsafe { self.ptr.as_ptr().add(offset).cast() }
+    }
+}
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: `self.ptr` is allocated with `self.layout`.
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::extended_capabilities::{self, ExtendedCapability};
+    use crate::Registers;
+
+    #[test]
+    fn registers_layout() {
+        let mmio = Mmio::new();
+        let mut r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+
+        let c = &r.capability;
+        assert_eq!(
+            usize::from(c.caplength.read_volatile().get()),
+            OPERATIONAL_OFFSET
+        );
+        assert_eq!(c.hciversion.read_volatile().get(), 0x0110);
+        assert_eq!(
+            c.hcsparams1.read_volatile().number_of_interrupts(),
+            NUM_INTERRUPTERS
+        );
+        assert_eq!(
+            c.hcsparams2.read_volatile().max_scratchpad_buffers(),
+            NUM_SCRATCHPAD_BUFFERS
+        );
+        assert!(c.hccparams1.read_volatile().addressing_capability());
+
+        assert_eq!(r.operational.pagesize.read_volatile().get(), 1);
+        assert!(r.port_register_set.read_volatile_at(3).portsc.port_power());
+
+        r.doorbell.update_volatile_at(0, |d| {
+            d.set_doorbell_target(0);
+        });
+        r.operational.usbcmd.update_volatile(|u| {
+            u.set_run_stop();
+        });
+        assert_eq!(mmio.read_u32(OPERATIONAL_OFFSET), 1);
+
+        r.interrupter_register_set
+            .interrupter_mut(0)
+            .erstsz
+            .update_volatile(|s| s.set(1));
+        assert_eq!(mmio.read_u32(RUNTIME_OFFSET + 0x28), 1);
+    }
+
+    #[test]
+    fn extended_capabilities_list() {
+        let mmio = Mmio::new();
+        let r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+        let mut l = unsafe {
+            extended_capabilities::List::new(
+                mmio.base(),
+                r.capability.hccparams1.read_volatile(),
+                mmio.mapper(),
+            )
+        }
+        .unwrap();
+
+        let mut protocols = 0;
+        for (i, c) in (&mut l).into_iter().enumerate() {
+            match c.unwrap() {
+                ExtendedCapability::UsbLegacySupport(_) => assert_eq!(i, 0),
+                ExtendedCapability::XhciSupportedProtocol(p) => {
+                    let h = p.header.read_volatile();
+                    assert_eq!(h.name_string(), u32::from_le_bytes(*b"USB "));
+                    assert_eq!(h.compatible_port_count(), 2);
+                    protocols += 1;
+                }
+                c => panic!("Unexpected capability: {:?}", c),
+            }
+        }
+        assert_eq!(protocols, 2);
+    }
+}
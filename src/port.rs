@@ -0,0 +1,553 @@
+//! Root Hub Ports.
+//!
+//! [`Port`] is a non-blocking state machine which connects a device to a Root Hub Port. It resets
+//! the port when a device is connected, and reports the state of the port.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::port::{Port, Protocol, State};
+//! # use core::num::NonZeroUsize;
+//! # use xhci::accessor::Mapper;
+//! # use xhci::extended_capabilities::List;
+//! #
+//! # #[derive(Clone)]
+//! # struct MemoryMapper;
+//! # impl Mapper for MemoryMapper {
+//! #     unsafe fn map(&mut self, phys_base: usize, bytes: usize) -> NonZeroUsize {
+//! #         unimplemented!()
+//! #     }
+//! #
+//! #     fn unmap(&mut self, virt_base: usize, bytes: usize) {
+//! #         unimplemented!()
+//! #     }
+//! # }
+//! #
+//! # let (mmio_base, mapper) = (0x1000, MemoryMapper);
+//! let mut r = unsafe { xhci::Registers::new(mmio_base, mapper.clone()) };
+//! let mut l = unsafe { List::new(mmio_base, r.capability.hccparams1.read_volatile(), mapper) }
+//!     .expect("The xHC does not have the xHCI Extended Capabilities.");
+//!
+//! let protocol = Protocol::find(&mut l, 1).expect("The protocol of the port is unknown.");
+//! let mut port = Port::new(1, protocol);
+//!
+//! // Call this method each time a Port Status Change Event for the port is received.
+//! if let State::Enabled(speed) = port.update(&mut r.port_register_set) {
+//!     // Enable a Device Slot and address the device.
+//! }
+//! ```
+
+use crate::extended_capabilities::xhci_supported_protocol::{BitRate, PsiType};
+use crate::extended_capabilities::{ExtendedCapability, List, XhciSupportedProtocol};
+use crate::registers::operational::{
+    PortLinkState, PortRegisterSet, PortStatusAndControlRegister, PortStatusAndControlWrite,
+};
+use crate::usb::Speed;
+use accessor::{array, Mapper};
+
+/// A state machine of a Root Hub Port.
+#[derive(Copy, Clone, Debug)]
+pub struct Port {
+    number: u8,
+    protocol: Protocol,
+    state: State,
+}
+impl Port {
+    /// Creates a state machine of the Root Hub Port `number`, which starts with
+    /// [`State::Disconnected`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `number` is 0.
+    #[must_use]
+    pub fn new(number: u8, protocol: Protocol) -> Self {
+        assert_ne!(number, 0, "The port number must not be 0.");
+
+        Self {
+            number,
+            protocol,
+            state: State::Disconnected,
+        }
+    }
+
+    /// Returns the port number.
+    #[must_use]
+    pub fn number(self) -> u8 {
+        self.number
+    }
+
+    /// Returns the protocol of the port.
+    #[must_use]
+    pub fn protocol(self) -> Protocol {
+        self.protocol
+    }
+
+    /// Returns the current state.
+    #[must_use]
+    pub fn state(self) -> State {
+        self.state
+    }
+
+    /// Reads the Port Status and Control Register, acknowledges the change bits, and advances the
+    /// state.
+    ///
+    /// Call this method each time a Port Status Change Event for this port is received, and once
+    /// after the xHC starts running. This method never waits.
+    ///
+    /// When a device is connected, a USB 2.0 port is reset. A USB 3.x port is enabled by the xHC
+    /// without a reset, and a Warm Reset is issued only if the link fails to train.
+    pub fn update<M>(&mut self, ports: &mut array::ReadWrite<PortRegisterSet, M>) -> State
+    where
+        M: Mapper,
+    {
+        let portsc = self.read(ports);
+
+        let (state, reset) = if !portsc.current_connect_status() {
+            (State::Disconnected, false)
+        } else if portsc.connect_status_change() || self.state == State::Disconnected {
+            self.connected(portsc)
+        } else if portsc.port_enabled_disabled() && !portsc.port_reset() {
+            (State::Enabled(portsc.port_speed()), false)
+        } else if self.protocol == Protocol::Usb3
+            && self.state == State::Resetting
+            && portsc.port_link_state_change()
+            && !portsc.port_reset()
+            && link_failed(portsc)
+        {
+            // The link failed to train after the device was connected.
+            (State::Resetting, true)
+        } else if self.state == State::Resetting
+            && !portsc.port_reset_change()
+            && !portsc.warm_port_reset_change()
+        {
+            (State::Resetting, false)
+        } else {
+            (State::Error, false)
+        };
+
+        let protocol = self.protocol;
+        PortRegisterSet::update_portsc_volatile_at(ports, self.index(), |_, w| {
+            w.acknowledge_all(portsc);
+
+            if reset {
+                start_reset(w, protocol);
+            }
+        });
+
+        self.state = state;
+        self.state
+    }
+
+    /// Resets the port to re-enumerate the connected device.
+    ///
+    /// A USB 2.0 port is reset with the Port Reset bit, and a USB 3.x port is reset with the Warm
+    /// Port Reset bit. Nothing happens if no device is connected.
+    pub fn reset<M>(&mut self, ports: &mut array::ReadWrite<PortRegisterSet, M>) -> State
+    where
+        M: Mapper,
+    {
+        if self.read(ports).current_connect_status() {
+            let protocol = self.protocol;
+            PortRegisterSet::update_portsc_volatile_at(ports, self.index(), |_, w| {
+                start_reset(w, protocol);
+            });
+
+            self.state = State::Resetting;
+        } else {
+            self.state = State::Disconnected;
+        }
+
+        self.state
+    }
+
+    /// Requests a transition to the link state `target` by writing it with the Port Link State
+    /// Write Strobe bit.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error without writing to the register if software may not request
+    /// the transition from the current link state. See [`Protocol::can_request`].
+    pub fn request_port_link_state<M>(
+        self,
+        ports: &mut array::ReadWrite<PortRegisterSet, M>,
+        target: PortLinkState,
+    ) -> Result<(), InvalidTransition>
+    where
+        M: Mapper,
+    {
+        let from = self.read(ports).port_link_state();
+
+        if !from.is_some_and(|from| self.protocol.can_request(from, target)) {
+            return Err(InvalidTransition { from, to: target });
+        }
+
+        PortRegisterSet::update_portsc_volatile_at(ports, self.index(), |_, w| {
+            w.write_port_link_state(target);
+        });
+
+        Ok(())
+    }
+
+    /// Returns the next state and whether the port must be reset.
+    fn connected(self, portsc: PortStatusAndControlRegister) -> (State, bool) {
+        match self.protocol {
+            Protocol::Usb2 => (State::Resetting, true),
+            Protocol::Usb3 if portsc.port_enabled_disabled() => {
+                (State::Enabled(portsc.port_speed()), false)
+            }
+            Protocol::Usb3 if link_failed(portsc) => (State::Resetting, true),
+            // The link is still training.
+            Protocol::Usb3 => (State::Resetting, false),
+        }
+    }
+
+    fn read<M>(self, ports: &array::ReadWrite<PortRegisterSet, M>) -> PortStatusAndControlRegister
+    where
+        M: Mapper,
+    {
+        ports.read_volatile_at(self.index()).portsc
+    }
+
+    fn index(self) -> usize {
+        usize::from(self.number) - 1
+    }
+}
+
+/// The state of a Root Hub Port.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum State {
+    /// No device is connected.
+    Disconnected,
+    /// The port is being reset, or the link is being trained.
+    Resetting,
+    /// The port is enabled. The value is the Port Speed field, which is a Protocol Speed ID Value.
+    Enabled(u8),
+    /// The port failed to be enabled, or it was disabled because of an error.
+    ///
+    /// Call [`Port::reset`] to retry.
+    Error,
+}
+
+/// The protocol of a Root Hub Port.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum Protocol {
+    /// USB 2.0 or earlier.
+    Usb2,
+    /// USB 3.x.
+    Usb3,
+}
+impl Protocol {
+    /// Returns the protocol of the Root Hub Port `port` by looking up the xHCI Supported Protocol
+    /// Capabilities in `list`.
+    ///
+    /// This method returns [`None`] if no xHCI Supported Protocol Capability covers the port.
+    pub fn find<M>(list: &mut List<M>, port: u8) -> Option<Self>
+    where
+        M: Mapper + Clone,
+    {
+        list.into_iter().find_map(|c| match c {
+            Ok(ExtendedCapability::XhciSupportedProtocol(p)) if covers(&p, port) => {
+                if p.header.read_volatile().major_revision() >= 3 {
+                    Some(Self::Usb3)
+                } else {
+                    Some(Self::Usb2)
+                }
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if software may request the transition of the link state of a port of this
+    /// protocol from `from` to `to`.
+    ///
+    /// The allowed transitions are:
+    ///
+    /// - U0 from U1, U2, U3, or Resume, which wakes the link up.
+    /// - U2 from U0 on a USB 2.0 port, which enters the L1 state.
+    /// - U3 from U0, U1, or U2, which suspends the link.
+    /// - Resume from U3 on a USB 2.0 port. Software writes U0 after the resume signaling.
+    /// - Disabled from any state other than Disabled on a USB 3.x port.
+    /// - `RxDetect` from Disabled on a USB 3.x port.
+    #[must_use]
+    pub fn can_request(self, from: PortLinkState, to: PortLinkState) -> bool {
+        use PortLinkState::{Disabled, Resume, RxDetect, U0, U1, U2, U3};
+
+        match (self, to) {
+            (_, U0) => matches!(from, U1 | U2 | U3 | Resume),
+            (Self::Usb2, U2) => from == U0,
+            (_, U3) => matches!(from, U0 | U1 | U2),
+            (Self::Usb2, Resume) => from == U3,
+            (Self::Usb3, Disabled) => from != Disabled,
+            (Self::Usb3, RxDetect) => from == Disabled,
+            _ => false,
+        }
+    }
+}
+
+/// An error returned when software may not request a link state transition.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct InvalidTransition {
+    /// The current link state, or [`None`] if it is a reserved value.
+    pub from: Option<PortLinkState>,
+    /// The requested link state.
+    pub to: PortLinkState,
+}
+
+/// Returns `true` if the link of a USB 3.x port needs a Warm Reset to recover.
+fn link_failed(portsc: PortStatusAndControlRegister) -> bool {
+    matches!(
+        portsc.port_link_state(),
+        Some(PortLinkState::Inactive | PortLinkState::ComplianceMode)
+    )
+}
+
+fn start_reset(w: &mut PortStatusAndControlWrite, protocol: Protocol) {
+    match protocol {
+        Protocol::Usb2 => w.port_reset(),
+        Protocol::Usb3 => w.warm_port_reset(),
+    };
+}
+
+/// The speed of a Root Hub Port resolved from a Protocol Speed ID Value.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct PortSpeed {
+    /// The bit rate in bits per second. For an asymmetric speed, this is the receive bit rate.
+    pub bits_per_second: u64,
+    /// The Major Revision of the protocol in BCD, such as `0x03` for USB 3.1.
+    pub major_revision: u8,
+    /// The Minor Revision of the protocol in BCD, such as `0x10` for USB 3.1.
+    pub minor_revision: u8,
+    /// The speed of the device.
+    pub speed: Speed,
+}
+impl PortSpeed {
+    /// Resolves the Protocol Speed ID Value `psiv` of the Root Hub Port `port`.
+    ///
+    /// `psiv` is the value of the Port Speed field of the Port Status and Control Register, or the
+    /// value of [`State::Enabled`]. This method finds the xHCI Supported Protocol Capability
+    /// covering `port` in `list`, and looks up its Protocol Speed ID Dwords. If the capability has
+    /// no Protocol Speed ID Dword, the default Protocol Speed ID Values are used.
+    ///
+    /// This method returns [`None`] if no capability covers the port, or if `psiv` is not defined
+    /// for the protocol of the port. A Protocol Speed ID Dword with the reserved PSI Type does not
+    /// define any value.
+    pub fn resolve<M>(list: &mut List<M>, port: u8, psiv: u8) -> Option<Self>
+    where
+        M: Mapper + Clone,
+    {
+        let p = list.into_iter().find_map(|c| match c {
+            Ok(ExtendedCapability::XhciSupportedProtocol(p)) if covers(&p, port) => Some(p),
+            _ => None,
+        })?;
+        let h = p.header.read_volatile();
+
+        let bits_per_second = match &p.psis {
+            Some(_) => {
+                let psi = p.protocol_speed_ids().find(|psi| {
+                    psi.protocol_speed_id_value() == psiv
+                        && psi
+                            .try_psi_type()
+                            .is_some_and(|t| t != PsiType::AsymmetricTx)
+                })?;
+
+                let unit = match psi.protocol_speed_id_exponent() {
+                    BitRate::Bits => 1,
+                    BitRate::Kb => 1_000,
+                    BitRate::Mb => 1_000_000,
+                    BitRate::Gb => 1_000_000_000,
+                };
+
+                u64::from(psi.protocol_speed_id_mantissa()) * unit
+            }
+            None => default_bit_rate(h.major_revision(), psiv)?,
+        };
+
+        let speed = if h.major_revision() < 3 {
+            match bits_per_second {
+                0..=1_500_000 => Speed::Low,
+                1_500_001..=12_000_000 => Speed::Full,
+                _ => Speed::High,
+            }
+        } else if bits_per_second > 5_000_000_000 {
+            Speed::SuperSpeedPlus
+        } else {
+            Speed::SuperSpeed
+        };
+
+        Some(Self {
+            bits_per_second,
+            major_revision: h.major_revision(),
+            minor_revision: h.minor_revision(),
+            speed,
+        })
+    }
+}
+
+/// Returns the bit rate of the default Protocol Speed ID Value `psiv`.
+fn default_bit_rate(major_revision: u8, psiv: u8) -> Option<u64> {
+    match (major_revision >= 3, psiv) {
+        (false, 1) => Some(12_000_000),
+        (false, 2) => Some(1_500_000),
+        (false, 3) => Some(480_000_000),
+        (true, 4) => Some(5_000_000_000),
+        (true, 5 | 6) => Some(10_000_000_000),
+        (true, 7) => Some(20_000_000_000),
+        _ => None,
+    }
+}
+
+fn covers<M>(p: &XhciSupportedProtocol<M>, port: u8) -> bool
+where
+    M: Mapper + Clone,
+{
+    p.header.read_volatile().ports().contains(&port)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock::controller::Controller;
+    use crate::mock::Mmio;
+    use crate::mock::{EXTENDED_CAPABILITIES_OFFSET, OPERATIONAL_OFFSET};
+    use crate::Registers;
+
+    #[test]
+    fn enumerate_ports() {
+        let mmio = Mmio::new();
+        let mut xhc = unsafe { Controller::new(mmio.clone()) };
+        let mut r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+        let mut l = unsafe {
+            List::new(
+                mmio.base(),
+                r.capability.hccparams1.read_volatile(),
+                mmio.mapper(),
+            )
+        }
+        .unwrap();
+
+        assert_eq!(Protocol::find(&mut l, 2), Some(Protocol::Usb2));
+        assert_eq!(Protocol::find(&mut l, 3), Some(Protocol::Usb3));
+        assert_eq!(Protocol::find(&mut l, 5), None);
+
+        let mut usb2 = Port::new(1, Protocol::Usb2);
+        let mut usb3 = Port::new(3, Protocol::Usb3);
+
+        xhc.attach(1, Speed::High);
+        assert_eq!(usb2.update(&mut r.port_register_set), State::Resetting);
+        xhc.step();
+        assert_eq!(usb2.update(&mut r.port_register_set), State::Enabled(3));
+        xhc.step();
+
+        let p = r.port_register_set.read_volatile_at(0).portsc;
+        assert!(p.port_enabled_disabled());
+        assert!(!p.connect_status_change());
+        assert!(!p.port_reset_change());
+
+        xhc.attach(3, Speed::SuperSpeed);
+        assert_eq!(usb3.update(&mut r.port_register_set), State::Enabled(4));
+
+        xhc.detach(1);
+        assert_eq!(usb2.update(&mut r.port_register_set), State::Disconnected);
+
+        assert_eq!(usb3.reset(&mut r.port_register_set), State::Resetting);
+        xhc.step();
+        assert_eq!(usb3.update(&mut r.port_register_set), State::Enabled(4));
+        xhc.step();
+
+        let suspend = usb3.request_port_link_state(&mut r.port_register_set, PortLinkState::U3);
+        assert_eq!(suspend, Ok(()));
+        xhc.step();
+        assert_eq!(
+            usb3.request_port_link_state(&mut r.port_register_set, PortLinkState::RxDetect),
+            Err(InvalidTransition {
+                from: Some(PortLinkState::U3),
+                to: PortLinkState::RxDetect
+            })
+        );
+        assert_eq!(
+            usb3.request_port_link_state(&mut r.port_register_set, PortLinkState::U0),
+            Ok(())
+        );
+        xhc.step();
+
+        let p = r.port_register_set.read_volatile_at(2).portsc;
+        assert_eq!(p.port_link_state(), Some(PortLinkState::U0));
+        assert!(p.port_link_state_change());
+    }
+
+    #[test]
+    fn warm_reset_after_link_training_fails() {
+        const PORTSC: usize = OPERATIONAL_OFFSET + 0x420;
+        const CCS: u32 = 1;
+        const PP: u32 = 1 << 9;
+        const CSC: u32 = 1 << 17;
+        const PLC: u32 = 1 << 22;
+        const WPR: u32 = 1 << 31;
+
+        let mmio = Mmio::new();
+        let mut r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+        let mut usb3 = Port::new(3, Protocol::Usb3);
+
+        // The device is connected while the link is in the Polling state.
+        mmio.write_u32(PORTSC, CCS | PP | CSC | 7 << 5);
+        assert_eq!(usb3.update(&mut r.port_register_set), State::Resetting);
+        assert_eq!(mmio.read_u32(PORTSC) & WPR, 0);
+
+        // The link fails to train.
+        mmio.write_u32(PORTSC, CCS | PP | PLC | 6 << 5);
+        assert_eq!(usb3.update(&mut r.port_register_set), State::Resetting);
+        assert_eq!(mmio.read_u32(PORTSC) & (WPR | PLC), WPR | PLC);
+    }
+
+    #[test]
+    fn resolve_speeds() {
+        let mmio = Mmio::new();
+
+        // Give the USB 3.1 ports a Protocol Speed ID Dword: PSIV 5, 10 Gb/s, SuperSpeedPlus.
+        let x = EXTENDED_CAPABILITIES_OFFSET + 0x20;
+        mmio.write_u32(x + 8, mmio.read_u32(x + 8) | 1 << 28);
+        mmio.write_u32(x + 0x10, 10 << 16 | 1 << 14 | 1 << 8 | 3 << 4 | 5);
+
+        let r = unsafe { Registers::new(mmio.base(), mmio.mapper()) };
+        let mut l = unsafe {
+            List::new(
+                mmio.base(),
+                r.capability.hccparams1.read_volatile(),
+                mmio.mapper(),
+            )
+        }
+        .unwrap();
+
+        assert_eq!(
+            PortSpeed::resolve(&mut l, 2, 3),
+            Some(PortSpeed {
+                bits_per_second: 480_000_000,
+                major_revision: 2,
+                minor_revision: 0,
+                speed: Speed::High,
+            })
+        );
+        assert_eq!(
+            PortSpeed::resolve(&mut l, 1, 2).map(|s| s.speed),
+            Some(Speed::Low)
+        );
+        assert_eq!(
+            PortSpeed::resolve(&mut l, 4, 5),
+            Some(PortSpeed {
+                bits_per_second: 10_000_000_000,
+                major_revision: 3,
+                minor_revision: 0x10,
+                speed: Speed::SuperSpeedPlus,
+            })
+        );
+
+        // The explicit Protocol Speed ID Dwords replace the default ones.
+        assert_eq!(PortSpeed::resolve(&mut l, 3, 4), None);
+        assert_eq!(PortSpeed::resolve(&mut l, 1, 4), None);
+        assert_eq!(PortSpeed::resolve(&mut l, 5, 1), None);
+
+        // The PSI Type 1 is reserved.
+        mmio.write_u32(x + 0x10, 10 << 16 | 1 << 14 | 1 << 8 | 1 << 6 | 3 << 4 | 5);
+        assert_eq!(PortSpeed::resolve(&mut l, 4, 5), None);
+    }
+}
@@ -119,7 +119,7 @@ impl_debug_from_methods! {
 
 /// USB Status Register
 #[repr(transparent)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct UsbStatusRegister(u32);
 impl UsbStatusRegister {
     ro_bit!(0, hc_halted, "HC Halted");
@@ -333,6 +333,27 @@ impl PortRegisterSet {
             mapper,
         )
     }
+
+    /// Reads the Port Status and Control Register of the port `i + 1`, and writes the value built
+    /// by `f`.
+    ///
+    /// `f` receives the read value and a [`PortStatusAndControlWrite`] created from it. The other
+    /// registers of the Port Register Set are written back as read.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `i` is out of range.
+    pub fn update_portsc_volatile_at<M, F>(ports: &mut array::ReadWrite<Self, M>, i: usize, f: F)
+    where
+        M: Mapper,
+        F: FnOnce(PortStatusAndControlRegister, &mut PortStatusAndControlWrite),
+    {
+        ports.update_volatile_at(i, |r| {
+            let mut w = PortStatusAndControlWrite::new(r.portsc);
+            f(r.portsc, &mut w);
+            r.portsc = w.get();
+        });
+    }
 }
 
 /// Port Status and Control Register
@@ -344,7 +365,25 @@ impl PortStatusAndControlRegister {
     rw1c_bit!(1, port_enabled_disabled, "Port Enabled/Disabled");
     ro_bit!(3, over_current_active, "Over-current Active");
     rw1s_bit!(4, port_reset, "Port Reset");
-    rw_field!(5..=8, port_link_state, "Port Link State", u8);
+
+    /// Returns the value of the Port Link State field.
+    ///
+    /// This field returns [`None`] if the value means `Reserved`.
+    #[must_use]
+    pub fn port_link_state(self) -> Option<PortLinkState> {
+        let s = self.0.get_bits(5..=8);
+        FromPrimitive::from_u32(s)
+    }
+
+    /// Sets the value of the Port Link State field.
+    ///
+    /// Use [`PortStatusAndControlWrite::write_port_link_state`] to request a link state
+    /// transition, which also requires the Port Link State Write Strobe bit.
+    pub fn set_port_link_state(&mut self, s: PortLinkState) -> &mut Self {
+        self.0.set_bits(5..=8, s as _);
+        self
+    }
+
     rw_bit!(9, port_power, "Port Power");
     ro_field!(10..=13, port_speed, "Port Speed", u8);
     rw_field!(
@@ -407,6 +446,189 @@ impl_debug_from_methods! {
     }
 }
 
+/// A value to write to the Port Status and Control Register.
+///
+/// The Port Status and Control Register mixes RW, RW1C, and RW1S bits, so writing back a read
+/// value may disable the port or clear the change bits which have not been handled yet. This
+/// struct starts from a read value whose RW1C and RW1S bits are all 0, so only the changes
+/// requested explicitly take effect.
+///
+/// # Examples
+///
+/// ```no_run
+/// use xhci::registers::operational::{PortChange, PortRegisterSet};
+/// # use core::num::NonZeroUsize;
+/// # use xhci::accessor::Mapper;
+/// #
+/// # #[derive(Clone)]
+/// # struct MemoryMapper;
+/// # impl Mapper for MemoryMapper {
+/// #     unsafe fn map(&mut self, phys_base: usize, bytes: usize) -> NonZeroUsize {
+/// #         unimplemented!()
+/// #     }
+/// #
+/// #     fn unmap(&mut self, virt_base: usize, bytes: usize) {
+/// #         unimplemented!()
+/// #     }
+/// # }
+/// #
+/// # let mut r = unsafe { xhci::Registers::new(0x1000, MemoryMapper) };
+/// PortRegisterSet::update_portsc_volatile_at(&mut r.port_register_set, 0, |_, w| {
+///     w.acknowledge(PortChange::ConnectStatus).port_reset();
+/// });
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct PortStatusAndControlWrite(PortStatusAndControlRegister);
+impl PortStatusAndControlWrite {
+    /// The bits which are preserved from the read value. These are the RO bits and the RW bits.
+    const PRESERVED: u32 =
+        1 | 1 << 3 | 0xf << 5 | 1 << 9 | 0xf << 10 | 0b11 << 14 | 0b111 << 25 | 1 << 30;
+
+    /// Creates a value which does not change the state of the port if it is written as is.
+    #[must_use]
+    pub fn new(read: PortStatusAndControlRegister) -> Self {
+        Self(PortStatusAndControlRegister(read.0 & Self::PRESERVED))
+    }
+
+    /// Acknowledges the change `c` by writing 1 to the corresponding RW1C bit.
+    pub fn acknowledge(&mut self, c: PortChange) -> &mut Self {
+        self.0 .0.set_bit(c.bit(), true);
+        self
+    }
+
+    /// Acknowledges all the changes reported by `read`.
+    pub fn acknowledge_all(&mut self, read: PortStatusAndControlRegister) -> &mut Self {
+        self.0 .0 |= read.0 & PortChange::MASK;
+        self
+    }
+
+    /// Disables the port by writing 1 to the Port Enabled/Disabled bit.
+    pub fn disable_port(&mut self) -> &mut Self {
+        self.0.clear_port_enabled_disabled();
+        self
+    }
+
+    /// Resets the port by writing 1 to the Port Reset bit.
+    pub fn port_reset(&mut self) -> &mut Self {
+        self.0.set_port_reset();
+        self
+    }
+
+    /// Performs a Warm Reset by writing 1 to the Warm Port Reset bit. This is valid only for USB3
+    /// ports.
+    pub fn warm_port_reset(&mut self) -> &mut Self {
+        self.0.set_warm_port_reset();
+        self
+    }
+
+    /// Requests a transition to the link state `s` by writing it to the Port Link State field
+    /// together with the Port Link State Write Strobe bit.
+    ///
+    /// This method does not check whether software may request the transition. See
+    /// [`Port::request_port_link_state`](crate::port::Port::request_port_link_state) for the
+    /// checked version.
+    pub fn write_port_link_state(&mut self, s: PortLinkState) -> &mut Self {
+        self.0
+            .set_port_link_state(s)
+            .set_port_link_state_write_strobe();
+        self
+    }
+
+    /// Sets the Port Power bit.
+    pub fn set_port_power(&mut self) -> &mut Self {
+        self.0.set_port_power();
+        self
+    }
+
+    /// Clears the Port Power bit.
+    pub fn clear_port_power(&mut self) -> &mut Self {
+        self.0.clear_port_power();
+        self
+    }
+
+    /// Sets the value of the Port Indicator Control field.
+    pub fn set_port_indicator_control(&mut self, i: PortIndicator) -> &mut Self {
+        self.0.set_port_indicator_control(i);
+        self
+    }
+
+    /// Sets the Wake on Connect Enable bit.
+    pub fn set_wake_on_connect_enable(&mut self) -> &mut Self {
+        self.0.set_wake_on_connect_enable();
+        self
+    }
+
+    /// Clears the Wake on Connect Enable bit.
+    pub fn clear_wake_on_connect_enable(&mut self) -> &mut Self {
+        self.0.clear_wake_on_connect_enable();
+        self
+    }
+
+    /// Sets the Wake on Disconnect Enable bit.
+    pub fn set_wake_on_disconnect_enable(&mut self) -> &mut Self {
+        self.0.set_wake_on_disconnect_enable();
+        self
+    }
+
+    /// Clears the Wake on Disconnect Enable bit.
+    pub fn clear_wake_on_disconnect_enable(&mut self) -> &mut Self {
+        self.0.clear_wake_on_disconnect_enable();
+        self
+    }
+
+    /// Sets the Wake on Over-Current Enable bit.
+    pub fn set_wake_on_over_current_enable(&mut self) -> &mut Self {
+        self.0.set_wake_on_over_current_enable();
+        self
+    }
+
+    /// Clears the Wake on Over-Current Enable bit.
+    pub fn clear_wake_on_over_current_enable(&mut self) -> &mut Self {
+        self.0.clear_wake_on_over_current_enable();
+        self
+    }
+
+    /// Returns the value to write.
+    #[must_use]
+    pub fn get(self) -> PortStatusAndControlRegister {
+        self.0
+    }
+}
+
+/// A change reported by the Port Status and Control Register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PortChange {
+    /// Connect Status Change.
+    ConnectStatus,
+    /// Port Enabled/Disabled Change.
+    PortEnabledDisabled,
+    /// Warm Port Reset Change.
+    WarmPortReset,
+    /// Over-Current Change.
+    OverCurrent,
+    /// Port Reset Change.
+    PortReset,
+    /// Port Link State Change.
+    PortLinkState,
+    /// Port Config Error Change.
+    PortConfigError,
+}
+impl PortChange {
+    const MASK: u32 = 0x7f << 17;
+
+    fn bit(self) -> usize {
+        match self {
+            Self::ConnectStatus => 17,
+            Self::PortEnabledDisabled => 18,
+            Self::WarmPortReset => 19,
+            Self::OverCurrent => 20,
+            Self::PortReset => 21,
+            Self::PortLinkState => 22,
+            Self::PortConfigError => 23,
+        }
+    }
+}
+
 /// Port Power Management Status and Control Register.
 #[repr(transparent)]
 #[derive(Copy, Clone)]
@@ -515,6 +737,48 @@ impl_debug_from_methods! {
     }
 }
 
+/// A type returned by [`PortStatusAndControlRegister::port_link_state`].
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, FromPrimitive)]
+pub enum PortLinkState {
+    /// U0 State.
+    U0 = 0,
+    /// U1 State.
+    U1 = 1,
+    /// U2 State.
+    U2 = 2,
+    /// U3 State (Device Suspended).
+    U3 = 3,
+    /// Disabled State.
+    Disabled = 4,
+    /// `RxDetect` State.
+    RxDetect = 5,
+    /// Inactive State.
+    Inactive = 6,
+    /// Polling State.
+    Polling = 7,
+    /// Recovery State.
+    Recovery = 8,
+    /// Hot Reset State.
+    HotReset = 9,
+    /// Compliance Mode State.
+    ComplianceMode = 10,
+    /// Test Mode State.
+    TestMode = 11,
+    /// Resume State.
+    Resume = 15,
+}
+impl TryFrom<u32> for PortLinkState {
+    type Error = u32;
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        FromPrimitive::from_u32(x).ok_or(x)
+    }
+}
+impl From<PortLinkState> for u32 {
+    fn from(s: PortLinkState) -> Self {
+        s as _
+    }
+}
+
 /// A type returned by [`PortStatusAndControlRegister::port_indicator_control`].
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, FromPrimitive)]
 pub enum PortIndicator {
@@ -559,16 +823,44 @@ pub enum L1Status {
 pub enum TestMode {
     /// Test mode not enabled.
     NotEnabled = 0,
-    /// Test J_STATE.
+    /// Test `J_STATE`.
     JState = 1,
-    /// Test K_STATE.
+    /// Test `K_STATE`.
     KState = 2,
-    /// Test SE0_NAK.
+    /// Test `SE0_NAK`.
     Se0Nak = 3,
     /// Test Packet.
     Pakcet = 4,
-    /// Test FORCE_ENABLE.
+    /// Test `FORCE_ENABLE`.
     ForceEnable = 5,
     /// Port Test Control Error.
     PortTestControlError = 15,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn portsc_write_is_neutral() {
+        // All the bits are set, including PED, PR, and the change bits.
+        let read = PortStatusAndControlRegister(0xffff_ffff);
+
+        let w = PortStatusAndControlWrite::new(read);
+        assert_eq!(w.get().0, 0x4e00_ffe9);
+
+        let mut w = PortStatusAndControlWrite::new(PortStatusAndControlRegister(0x0022_0203));
+        w.acknowledge(PortChange::ConnectStatus).port_reset();
+        let v = w.get();
+        assert!(v.connect_status_change());
+        assert!(!v.port_reset_change());
+        assert!(!v.port_enabled_disabled());
+        assert!(v.port_reset());
+        assert!(v.port_power());
+
+        let mut w = PortStatusAndControlWrite::new(PortStatusAndControlRegister(0x0022_0203));
+        w.acknowledge_all(PortStatusAndControlRegister(0x0022_0203))
+            .write_port_link_state(PortLinkState::U3);
+        assert_eq!(w.get().0, 0x0023_0261);
+    }
+}
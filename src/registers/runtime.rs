@@ -87,7 +87,7 @@ where
     /// correctly.
     pub unsafe fn new(mmio_base: usize, rtoff: RuntimeRegisterSpaceOffset, mapper: M) -> Self {
         let base = mmio_base + usize::try_from(rtoff.get()).unwrap() + 0x20;
-        assert!(base % 0x20 == 0, "base is not aligned");
+        assert!(base.trailing_zeros() >= 5, "base is not aligned");
 
         Self { base, mapper }
     }
@@ -163,7 +163,7 @@ where
 
 /// Interrupter Management Register.
 #[repr(transparent)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct InterrupterManagementRegister(u32);
 impl InterrupterManagementRegister {
     rw1c_bit!(0, interrupt_pending, "Interrupt Pending");
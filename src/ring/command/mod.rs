@@ -0,0 +1,204 @@
+//! Command Ring.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::ring::{command, trb, Segment};
+//!
+//! # let mut memory = [[0; 4]; 16];
+//! # let phys_base = 0x1000;
+//! let mut ring = command::Ring::new(Segment::new(&mut memory, phys_base));
+//!
+//! let addr = ring
+//!     .enqueue(trb::command::Noop::new().into())
+//!     .expect("The Command Ring is full.");
+//!
+//! // Ring the Doorbell 0, and wait for the Command Completion Event whose Command TRB Pointer is
+//! // `addr`.
+//! ```
+
+use super::trb::command::Allowed;
+use super::trb::event::{CommandCompletion, CompletionCode};
+use super::trb::Link;
+use super::{Full, Segment};
+use crate::registers::operational::CommandRingControlRegister;
+
+pub mod tracker;
+
+/// Command Ring.
+///
+/// The Command Ring consists of a single [`Segment`]. The last TRB of the Segment is reserved for
+/// a Link TRB which points to the first TRB of the Segment.
+#[derive(Debug)]
+pub struct Ring<'a> {
+    segment: Segment<'a>,
+    enqueue: usize,
+    dequeue: usize,
+    cycle_state: bool,
+}
+impl<'a> Ring<'a> {
+    /// Creates a new Command Ring.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `segment` contains less than 3 TRBs.
+    #[must_use]
+    pub fn new(segment: Segment<'a>) -> Self {
+        assert!(
+            segment.trb_count() >= 3,
+            "The Command Ring must contain at least 3 TRBs."
+        );
+
+        Self {
+            segment,
+            enqueue: 0,
+            dequeue: 0,
+            cycle_state: true,
+        }
+    }
+
+    /// Enqueues a Command TRB and returns the physical address of it.
+    ///
+    /// The Cycle bit of `trb` is overwritten with the Producer Cycle State. The returned address is
+    /// the one reported by [`CommandCompletion::command_trb_pointer`].
+    ///
+    /// Note that this method does not ring the Doorbell.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if there is no space for the TRB. Call
+    /// [`Ring::handle_completion`] to free the TRBs consumed by the xHC.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `trb` is a Link TRB. The Link TRB is written by this ring.
+    pub fn enqueue(&mut self, mut trb: Allowed) -> Result<u64, Full> {
+        assert!(
+            !matches!(trb, Allowed::Link(_)),
+            "The Link TRB is managed by the Command Ring."
+        );
+
+        if self.next(self.enqueue) == self.dequeue {
+            return Err(Full);
+        }
+
+        if self.cycle_state {
+            trb.set_cycle_bit();
+        } else {
+            trb.clear_cycle_bit();
+        }
+
+        let addr = self.segment.phys_addr(self.enqueue);
+        self.segment.write(self.enqueue, trb.into_raw());
+
+        self.enqueue += 1;
+        if self.enqueue == self.link_index() {
+            self.write_link();
+
+            self.enqueue = 0;
+            self.cycle_state = !self.cycle_state;
+        }
+
+        Ok(addr)
+    }
+
+    /// Frees the TRBs which the xHC has consumed.
+    ///
+    /// Call this method with every Command Completion Event generated for this ring. Events whose
+    /// Command TRB Pointer does not point to this ring are ignored.
+    pub fn handle_completion(&mut self, c: &CommandCompletion) {
+        if let Some(i) = self.segment.index_of(c.command_trb_pointer()) {
+            // The Command TRB Pointer of the Command Ring Stopped event points to the TRB which
+            // has not been executed yet.
+            self.dequeue = if c.completion_code() == Ok(CompletionCode::CommandRingStopped) {
+                i
+            } else {
+                self.next(i)
+            };
+        }
+    }
+
+    /// Sets the Command Ring Pointer and the Ring Cycle State of `crcr` to the start of this ring.
+    ///
+    /// The value must be written to the Command Ring Control Register before the xHC starts
+    /// processing this ring.
+    pub fn initialize_crcr(&self, crcr: &mut CommandRingControlRegister) {
+        crcr.set_command_ring_pointer(self.segment.phys_base());
+        crcr.set_ring_cycle_state();
+    }
+
+    fn write_link(&mut self) {
+        let mut l = Link::new();
+        l.set_ring_segment_pointer(self.segment.phys_base())
+            .set_toggle_cycle();
+
+        if self.cycle_state {
+            l.set_cycle_bit();
+        }
+
+        self.segment.write(self.link_index(), l.into_raw());
+    }
+
+    fn next(&self, i: usize) -> usize {
+        if i + 1 == self.link_index() {
+            0
+        } else {
+            i + 1
+        }
+    }
+
+    fn link_index(&self) -> usize {
+        self.segment.trb_count() - 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ring::trb::command::Noop;
+    use core::convert::TryFrom;
+
+    const BASE: u64 = 0x1000;
+
+    fn completion(addr: u64) -> CommandCompletion {
+        CommandCompletion::try_from([u32::try_from(addr).unwrap(), 0, 0x0100_0000, 0x0000_8401])
+            .unwrap()
+    }
+
+    #[test]
+    fn enqueue_until_full() {
+        let mut memory = [[0; 4]; 4];
+        let mut ring = Ring::new(Segment::new(&mut memory, BASE));
+
+        assert_eq!(ring.enqueue(Noop::new().into()), Ok(BASE));
+        assert_eq!(ring.enqueue(Noop::new().into()), Ok(BASE + 0x10));
+        assert_eq!(ring.enqueue(Noop::new().into()), Err(Full));
+
+        ring.handle_completion(&completion(BASE));
+        assert_eq!(ring.enqueue(Noop::new().into()), Ok(BASE + 0x20));
+    }
+
+    #[test]
+    fn link_trb_toggles_cycle() {
+        let mut memory = [[0; 4]; 4];
+        {
+            let mut ring = Ring::new(Segment::new(&mut memory, BASE));
+
+            for i in 0..3 {
+                let addr = ring.enqueue(Noop::new().into()).unwrap();
+                assert_eq!(addr, BASE + i * 0x10);
+                ring.handle_completion(&completion(addr));
+            }
+
+            assert_eq!(ring.enqueue(Noop::new().into()), Ok(BASE));
+        }
+
+        let link = Link::try_from(memory[3]).unwrap();
+        assert_eq!(link.ring_segment_pointer(), BASE);
+        assert!(link.toggle_cycle());
+        assert!(link.cycle_bit());
+
+        assert!(!Noop::try_from(memory[0]).unwrap().cycle_bit());
+        assert!(Noop::try_from(memory[2]).unwrap().cycle_bit());
+    }
+}
@@ -0,0 +1,313 @@
+//! Command tracker.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use core::time::Duration;
+//! use xhci::ring::command::tracker::{Outcome, Tracker};
+//! use xhci::ring::{command, trb, Segment};
+//!
+//! # let mut memory = [[0; 4]; 16];
+//! # let mut clock = || Duration::ZERO;
+//! # let c: trb::event::CommandCompletion = unimplemented!();
+//! let mut tracker = Tracker::<8>::new(command::Ring::new(Segment::new(&mut memory, 0x1000)));
+//!
+//! tracker
+//!     .submit(trb::command::EnableSlot::new().into(), &mut clock)
+//!     .expect("The Command Ring is full.");
+//!
+//! // Ring the Doorbell 0, and pass the Command Completion Event to the tracker.
+//! match tracker.handle_completion(&c) {
+//!     Ok(Outcome::SlotEnabled(slot_id)) => {
+//!         // Initialize the Device Slot.
+//!     }
+//!     Ok(Outcome::Failed { command, code }) => {
+//!         // Handle the error.
+//!     }
+//!     _ => {}
+//! }
+//! ```
+
+use super::Ring;
+use crate::controller::{Clock, DEFAULT_TIMEOUT};
+use crate::ring::trb::command::Allowed;
+use crate::ring::trb::event::{CommandCompletion, CompletionCode};
+use crate::ring::Full;
+use core::time::Duration;
+
+/// A Command Ring which records the commands in flight.
+///
+/// The tracker correlates each Command Completion Event with the command which generated it by
+/// the Command TRB Pointer, and returns the typed result of the command. Up to `N` commands can be
+/// in flight.
+#[derive(Debug)]
+pub struct Tracker<'a, const N: usize> {
+    ring: Ring<'a>,
+    pending: [Option<Pending>; N],
+    timeout: Duration,
+}
+impl<'a, const N: usize> Tracker<'a, N> {
+    /// Creates a new tracker which submits the commands to `ring`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `N == 0`.
+    #[must_use]
+    pub fn new(ring: Ring<'a>) -> Self {
+        assert_ne!(N, 0, "The tracker must be able to record a command.");
+
+        Self {
+            ring,
+            pending: [None; N],
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Returns the Command Ring.
+    #[must_use]
+    pub fn ring(&self) -> &Ring<'a> {
+        &self.ring
+    }
+
+    /// Returns the time after which a command is regarded as never completing.
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time after which a command is regarded as never completing.
+    ///
+    /// The default value is [`DEFAULT_TIMEOUT`].
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the number of the commands in flight.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.pending.iter().flatten().count()
+    }
+
+    /// Enqueues a command and returns the physical address of the Command TRB.
+    ///
+    /// Note that this method does not ring the Doorbell.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if there is no space for the TRB in the Command Ring,
+    /// or if `N` commands are already in flight.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `command` is a Link TRB.
+    pub fn submit(&mut self, command: Allowed, clock: &mut dyn Clock) -> Result<u64, Full> {
+        let slot = self.pending.iter_mut().find(|p| p.is_none()).ok_or(Full)?;
+        let address = self.ring.enqueue(command)?;
+
+        *slot = Some(Pending {
+            address,
+            command,
+            deadline: clock.now() + self.timeout,
+        });
+
+        Ok(address)
+    }
+
+    /// Returns the result of the command which generated `c`.
+    ///
+    /// Call this method with every Command Completion Event. The TRBs consumed by the xHC are
+    /// freed.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`UnknownCommand`] if no command in flight has the Command TRB Pointer
+    /// of `c`. This happens if the command has already expired, or if the event is spurious.
+    pub fn handle_completion(&mut self, c: &CommandCompletion) -> Result<Outcome, UnknownCommand> {
+        self.ring.handle_completion(c);
+
+        let code = c.completion_code();
+
+        // The Command TRB Pointer points to the command which has not been executed yet.
+        if code == Ok(CompletionCode::CommandRingStopped) {
+            return Ok(Outcome::RingStopped);
+        }
+
+        let address = c.command_trb_pointer();
+        let Pending { command, .. } = self
+            .pending
+            .iter_mut()
+            .find(|p| p.is_some_and(|p| p.address == address))
+            .and_then(Option::take)
+            .ok_or(UnknownCommand { address })?;
+
+        if code != Ok(CompletionCode::Success) {
+            return Ok(Outcome::Failed { command, code });
+        }
+
+        Ok(match command {
+            Allowed::EnableSlot(_) => Outcome::SlotEnabled(c.slot_id()),
+            Allowed::GetPortBandwidth(g) => {
+                Outcome::PortBandwidthContextFilled(g.port_bandwidth_context_pointer())
+            }
+            _ => Outcome::Completed(command),
+        })
+    }
+
+    /// Removes a command which has not completed within the timeout, and returns it.
+    ///
+    /// Call this method repeatedly until it returns [`None`]. After a command expires, abort the
+    /// Command Ring with the Command Abort bit of the Command Ring Control Register so that the
+    /// xHC skips it.
+    pub fn expired(&mut self, clock: &mut dyn Clock) -> Option<Expired> {
+        let now = clock.now();
+
+        self.pending
+            .iter_mut()
+            .find(|p| p.is_some_and(|p| p.deadline <= now))
+            .and_then(Option::take)
+            .map(|p| Expired {
+                address: p.address,
+                command: p.command,
+            })
+    }
+}
+
+/// The typed result of a command.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Outcome {
+    /// An Enable Slot Command completed successfully. The value is the ID of the enabled Device
+    /// Slot.
+    SlotEnabled(u8),
+    /// A Get Port Bandwidth Command completed successfully. The value is the physical address of
+    /// the Port Bandwidth Context filled by the xHC.
+    PortBandwidthContextFilled(u64),
+    /// The other command completed successfully.
+    Completed(Allowed),
+    /// The command failed.
+    Failed {
+        /// The command.
+        command: Allowed,
+        /// The value of the Completion Code field of the Command Completion Event TRB.
+        code: Result<CompletionCode, u8>,
+    },
+    /// The Command Ring is stopped.
+    ///
+    /// This is not the result of a command. The command pointed by the event is not executed yet,
+    /// and it is still in flight.
+    RingStopped,
+}
+
+/// A command which has not completed within the timeout.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Expired {
+    /// The physical address of the Command TRB.
+    pub address: u64,
+    /// The command.
+    pub command: Allowed,
+}
+
+/// A Command Completion Event whose Command TRB Pointer does not point to any command in flight.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct UnknownCommand {
+    /// The value of the Command TRB Pointer field.
+    pub address: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Pending {
+    address: u64,
+    command: Allowed,
+    deadline: Duration,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ring::trb::command::{AddressDevice, EnableSlot, GetPortBandwidth, Noop};
+    use crate::ring::Segment;
+    use core::convert::TryFrom;
+
+    const BASE: u64 = 0x1000;
+
+    fn completion(addr: u64, (code, slot_id): (u32, u32)) -> CommandCompletion {
+        CommandCompletion::try_from([
+            u32::try_from(addr).unwrap(),
+            0,
+            code << 24,
+            slot_id << 24 | 33 << 10 | 1,
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn typed_outcomes() {
+        let mut memory = [[0; 4]; 8];
+        let mut tracker = Tracker::<4>::new(Ring::new(Segment::new(&mut memory, BASE)));
+        let mut clock = || Duration::ZERO;
+
+        let mut get_port_bandwidth = GetPortBandwidth::new();
+        get_port_bandwidth.set_port_bandwidth_context_pointer(0x3000);
+
+        let enable_slot = tracker
+            .submit(EnableSlot::new().into(), &mut clock)
+            .unwrap();
+        let address_device = tracker
+            .submit(AddressDevice::new().into(), &mut clock)
+            .unwrap();
+        let bandwidth = tracker
+            .submit(get_port_bandwidth.into(), &mut clock)
+            .unwrap();
+        let noop = tracker.submit(Noop::new().into(), &mut clock).unwrap();
+        assert_eq!(tracker.submit(Noop::new().into(), &mut clock), Err(Full));
+
+        assert_eq!(
+            tracker.handle_completion(&completion(enable_slot, (1, 3))),
+            Ok(Outcome::SlotEnabled(3))
+        );
+
+        let Ok(Outcome::Failed { command, code }) =
+            tracker.handle_completion(&completion(address_device, (4, 3)))
+        else {
+            panic!("The Address Device Command must fail.");
+        };
+        assert!(matches!(command, Allowed::AddressDevice(_)));
+        assert_eq!(code, Ok(CompletionCode::UsbTransactionError));
+
+        assert_eq!(
+            tracker.handle_completion(&completion(bandwidth, (1, 0))),
+            Ok(Outcome::PortBandwidthContextFilled(0x3000))
+        );
+        assert_eq!(
+            tracker.handle_completion(&completion(noop, (24, 0))),
+            Ok(Outcome::RingStopped)
+        );
+        assert_eq!(tracker.pending(), 1);
+
+        assert!(matches!(
+            tracker.handle_completion(&completion(noop, (1, 0))),
+            Ok(Outcome::Completed(Allowed::Noop(_)))
+        ));
+        assert_eq!(
+            tracker.handle_completion(&completion(noop, (1, 0))),
+            Err(UnknownCommand { address: noop })
+        );
+    }
+
+    #[test]
+    fn commands_expire() {
+        let mut memory = [[0; 4]; 8];
+        let mut tracker = Tracker::<2>::new(Ring::new(Segment::new(&mut memory, BASE)));
+
+        let mut now = Duration::ZERO;
+        let noop = tracker.submit(Noop::new().into(), &mut || now).unwrap();
+
+        now = Duration::from_millis(999);
+        assert_eq!(tracker.expired(&mut || now), None);
+
+        now = Duration::from_secs(1);
+        assert_eq!(tracker.expired(&mut || now).map(|e| e.address), Some(noop));
+        assert_eq!(tracker.expired(&mut || now), None);
+        assert_eq!(tracker.pending(), 0);
+    }
+}
@@ -0,0 +1,460 @@
+//! Event dispatcher.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::ring::event::dispatch::{Dispatcher, Route, Router};
+//! use xhci::ring::trb::event::{PortStatusChange, TransferEvent};
+//!
+//! struct Keyboard;
+//! impl Dispatcher for Keyboard {
+//!     fn on_transfer(&mut self, e: &TransferEvent) {
+//!         // Parse the report.
+//!     }
+//! }
+//!
+//! struct RootHub;
+//! impl Dispatcher for RootHub {
+//!     fn on_port_status_change(&mut self, e: &PortStatusChange) {
+//!         // Handle the connection.
+//!     }
+//! }
+//!
+//! # let mut event_ring: xhci::ring::event::Ring<'_, '_> = unimplemented!();
+//! let mut keyboard = Keyboard;
+//! let mut root_hub = RootHub;
+//!
+//! let mut router = Router::<'_, 16>::new();
+//! router.set_default(&mut root_hub);
+//! router
+//!     .insert(Route::new(1, 3), &mut keyboard)
+//!     .unwrap_or_else(|_| panic!("Failed to add a route."));
+//!
+//! for e in event_ring.by_ref().flatten() {
+//!     router.dispatch(e);
+//! }
+//! ```
+
+use crate::ring::trb::event::{
+    Allowed, BandwidthRequest, CommandCompletion, DeviceNotification, Doorbell, HostController,
+    MfindexWrap, PortStatusChange, TransferEvent,
+};
+use core::fmt;
+
+/// A handler of the Event TRBs.
+///
+/// Each method is called with an event of the corresponding kind, and does nothing by default.
+/// Implement the methods for the events the handler is interested in.
+pub trait Dispatcher {
+    /// Calls the method corresponding to the kind of `e`.
+    fn dispatch(&mut self, e: Allowed) {
+        match e {
+            Allowed::TransferEvent(e) => self.on_transfer(&e),
+            Allowed::CommandCompletion(e) => self.on_command_completion(&e),
+            Allowed::PortStatusChange(e) => self.on_port_status_change(&e),
+            Allowed::BandwidthRequest(e) => self.on_bandwidth_request(&e),
+            Allowed::Doorbell(e) => self.on_doorbell(&e),
+            Allowed::HostController(e) => self.on_host_controller(&e),
+            Allowed::DeviceNotification(e) => self.on_device_notification(&e),
+            Allowed::MfindexWrap(e) => self.on_mfindex_wrap(&e),
+        }
+    }
+
+    /// Handles a Transfer Event.
+    fn on_transfer(&mut self, _e: &TransferEvent) {}
+
+    /// Handles a Command Completion Event.
+    fn on_command_completion(&mut self, _e: &CommandCompletion) {}
+
+    /// Handles a Port Status Change Event.
+    fn on_port_status_change(&mut self, _e: &PortStatusChange) {}
+
+    /// Handles a Bandwidth Request Event.
+    fn on_bandwidth_request(&mut self, _e: &BandwidthRequest) {}
+
+    /// Handles a Doorbell Event.
+    fn on_doorbell(&mut self, _e: &Doorbell) {}
+
+    /// Handles a Host Controller Event.
+    fn on_host_controller(&mut self, _e: &HostController) {}
+
+    /// Handles a Device Notification Event.
+    fn on_device_notification(&mut self, _e: &DeviceNotification) {}
+
+    /// Handles an MFINDEX Wrap Event.
+    fn on_mfindex_wrap(&mut self, _e: &MfindexWrap) {}
+
+    /// Returns whether the TRB at `address` belongs to a Transfer Ring of this handler.
+    ///
+    /// [`Router`] calls this method to find the handler of a Stream, as a Transfer Event does not
+    /// contain the Stream ID. Handlers routed with a Stream ID must override this method, e.g. with
+    /// [`Ring::contains`](crate::ring::transfer::Ring::contains). The default implementation
+    /// returns `true`.
+    fn contains_trb(&self, _address: u64) -> bool {
+        true
+    }
+}
+
+/// The key of a route of [`Router`].
+#[allow(clippy::struct_field_names)]
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Route {
+    slot_id: u8,
+    endpoint_id: u8,
+    stream_id: u16,
+}
+impl Route {
+    /// Creates a route of the events of an endpoint.
+    ///
+    /// `endpoint_id` is the Device Context Index of the endpoint. If it is 0, the route receives
+    /// the events of the Device Slot which are not routed to any endpoint, including the Device
+    /// Notification and Bandwidth Request Events.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot_id` is 0 or `endpoint_id` is greater than 31.
+    #[must_use]
+    pub fn new(slot_id: u8, endpoint_id: u8) -> Self {
+        assert_ne!(slot_id, 0, "The Slot ID must not be 0.");
+        assert!(endpoint_id <= 31, "The Device Context Index must be <= 31.");
+
+        Self {
+            slot_id,
+            endpoint_id,
+            stream_id: 0,
+        }
+    }
+
+    /// Creates a route of the events of a Stream.
+    ///
+    /// The handler of the route must override [`Dispatcher::contains_trb`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `slot_id` is 0, if `endpoint_id` is not between 1 and 31, or if
+    /// `stream_id` is 0.
+    #[must_use]
+    pub fn with_stream(slot_id: u8, endpoint_id: u8, stream_id: u16) -> Self {
+        assert_ne!(
+            endpoint_id, 0,
+            "The Default Control Endpoint has no Streams."
+        );
+        assert_ne!(stream_id, 0, "The Stream ID must not be 0.");
+
+        Self {
+            stream_id,
+            ..Self::new(slot_id, endpoint_id)
+        }
+    }
+
+    /// Returns the Slot ID.
+    #[must_use]
+    pub fn slot_id(self) -> u8 {
+        self.slot_id
+    }
+
+    /// Returns the Device Context Index of the endpoint. 0 means the whole Device Slot.
+    #[must_use]
+    pub fn endpoint_id(self) -> u8 {
+        self.endpoint_id
+    }
+
+    /// Returns the Stream ID. 0 means the route is not for a Stream.
+    #[must_use]
+    pub fn stream_id(self) -> u16 {
+        self.stream_id
+    }
+}
+
+/// A routing table which fans the events out to the handlers.
+///
+/// The table holds up to `N` routes. A Transfer Event is passed to the first handler found in the
+/// following order:
+///
+/// 1. The handler of a Stream of the endpoint, whose [`Dispatcher::contains_trb`] returns `true`
+///    for the TRB Pointer. Events generated by Event Data TRBs skip this step.
+/// 2. The handler of the endpoint.
+/// 3. The handler of the Device Slot.
+/// 4. The default handler.
+///
+/// Device Notification and Bandwidth Request Events are passed to the handler of the Device Slot
+/// or the default handler. The other events are passed to the default handler. Events without a
+/// handler are dropped.
+pub struct Router<'a, const N: usize> {
+    routes: [Option<(Route, &'a mut dyn Dispatcher)>; N],
+    default: Option<&'a mut dyn Dispatcher>,
+}
+impl<'a, const N: usize> Router<'a, N> {
+    /// Creates an empty routing table.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            routes: core::array::from_fn(|_| None),
+            default: None,
+        }
+    }
+
+    /// Sets the handler of the events which are not routed.
+    ///
+    /// The previous handler is returned.
+    pub fn set_default(
+        &mut self,
+        handler: &'a mut dyn Dispatcher,
+    ) -> Option<&'a mut dyn Dispatcher> {
+        self.default.replace(handler)
+    }
+
+    /// Adds a route.
+    ///
+    /// # Errors
+    ///
+    /// This method returns `handler` back if `route` already exists, or if the table already has
+    /// `N` routes.
+    pub fn insert(
+        &mut self,
+        route: Route,
+        handler: &'a mut dyn Dispatcher,
+    ) -> Result<(), &'a mut dyn Dispatcher> {
+        if self.position(route).is_some() {
+            return Err(handler);
+        }
+
+        match self.routes.iter_mut().find(|r| r.is_none()) {
+            Some(r) => {
+                *r = Some((route, handler));
+                Ok(())
+            }
+            None => Err(handler),
+        }
+    }
+
+    /// Removes a route and returns its handler.
+    pub fn remove(&mut self, route: Route) -> Option<&'a mut dyn Dispatcher> {
+        self.position(route)
+            .and_then(|i| self.routes[i].take())
+            .map(|(_, h)| h)
+    }
+
+    /// Removes all the routes of a Device Slot.
+    ///
+    /// Call this method when the Device Slot is disabled.
+    pub fn remove_slot(&mut self, slot_id: u8) {
+        for r in &mut self.routes {
+            if r.as_ref().is_some_and(|(k, _)| k.slot_id == slot_id) {
+                *r = None;
+            }
+        }
+    }
+
+    fn position(&self, route: Route) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|r| r.as_ref().is_some_and(|(k, _)| *k == route))
+    }
+
+    fn find(
+        &mut self,
+        f: impl Fn(Route, &dyn Dispatcher) -> bool,
+    ) -> Option<&mut (dyn Dispatcher + 'a)> {
+        let (_, h) = self
+            .routes
+            .iter_mut()
+            .flatten()
+            .find(|(k, h)| f(*k, &**h))?;
+        Some(&mut **h)
+    }
+
+    fn slot_or_default(&mut self, slot_id: u8) -> Option<&mut (dyn Dispatcher + 'a)> {
+        let route = Route {
+            slot_id,
+            endpoint_id: 0,
+            stream_id: 0,
+        };
+
+        if self.position(route).is_some() {
+            self.find(|k, _| k == route)
+        } else {
+            self.default.as_deref_mut()
+        }
+    }
+}
+impl<const N: usize> Dispatcher for Router<'_, N> {
+    fn on_transfer(&mut self, e: &TransferEvent) {
+        let (slot_id, endpoint_id) = (e.slot_id(), e.endpoint_id());
+        let stream = |k: Route, h: &dyn Dispatcher| {
+            k.slot_id == slot_id
+                && k.endpoint_id == endpoint_id
+                && k.stream_id != 0
+                && !e.event_data()
+                && h.contains_trb(e.trb_pointer())
+        };
+        let endpoint = Route {
+            slot_id,
+            endpoint_id,
+            stream_id: 0,
+        };
+
+        let handler = if self.find(stream).is_some() {
+            self.find(stream)
+        } else if self.position(endpoint).is_some() {
+            self.find(|k, _| k == endpoint)
+        } else {
+            self.slot_or_default(slot_id)
+        };
+
+        if let Some(h) = handler {
+            h.on_transfer(e);
+        }
+    }
+
+    fn on_command_completion(&mut self, e: &CommandCompletion) {
+        if let Some(h) = &mut self.default {
+            h.on_command_completion(e);
+        }
+    }
+
+    fn on_port_status_change(&mut self, e: &PortStatusChange) {
+        if let Some(h) = &mut self.default {
+            h.on_port_status_change(e);
+        }
+    }
+
+    fn on_bandwidth_request(&mut self, e: &BandwidthRequest) {
+        if let Some(h) = self.slot_or_default(e.slot_id()) {
+            h.on_bandwidth_request(e);
+        }
+    }
+
+    fn on_doorbell(&mut self, e: &Doorbell) {
+        if let Some(h) = &mut self.default {
+            h.on_doorbell(e);
+        }
+    }
+
+    fn on_host_controller(&mut self, e: &HostController) {
+        if let Some(h) = &mut self.default {
+            h.on_host_controller(e);
+        }
+    }
+
+    fn on_device_notification(&mut self, e: &DeviceNotification) {
+        if let Some(h) = self.slot_or_default(e.slot_id()) {
+            h.on_device_notification(e);
+        }
+    }
+
+    fn on_mfindex_wrap(&mut self, e: &MfindexWrap) {
+        if let Some(h) = &mut self.default {
+            h.on_mfindex_wrap(e);
+        }
+    }
+}
+impl<const N: usize> Default for Router<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<const N: usize> fmt::Debug for Router<'_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &RouteList(&self.routes))
+            .field("default", &self.default.is_some())
+            .finish()
+    }
+}
+
+struct RouteList<'r, 'a, const N: usize>(&'r [Option<(Route, &'a mut dyn Dispatcher)>; N]);
+impl<const N: usize> fmt::Debug for RouteList<'_, '_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().flatten().map(|(k, _)| k))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::TryFrom;
+
+    #[derive(Default)]
+    struct Counter {
+        transfers: usize,
+        notifications: usize,
+        port_status_changes: usize,
+        ring: Option<u64>,
+    }
+    impl Dispatcher for Counter {
+        fn on_transfer(&mut self, _e: &TransferEvent) {
+            self.transfers += 1;
+        }
+
+        fn on_device_notification(&mut self, _e: &DeviceNotification) {
+            self.notifications += 1;
+        }
+
+        fn on_port_status_change(&mut self, _e: &PortStatusChange) {
+            self.port_status_changes += 1;
+        }
+
+        fn contains_trb(&self, address: u64) -> bool {
+            self.ring.is_none_or(|r| address & !0xff == r)
+        }
+    }
+
+    fn transfer(addr: u32, (slot_id, endpoint_id): (u32, u32)) -> Allowed {
+        Allowed::try_from([
+            addr,
+            0,
+            1 << 24,
+            slot_id << 24 | endpoint_id << 16 | 32 << 10 | 1,
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn route_events() {
+        let mut stream1 = Counter {
+            ring: Some(0x1000),
+            ..Counter::default()
+        };
+        let mut stream2 = Counter {
+            ring: Some(0x2000),
+            ..Counter::default()
+        };
+        let mut endpoint = Counter::default();
+        let mut slot = Counter::default();
+        let mut default = Counter::default();
+
+        {
+            let mut router = Router::<'_, 4>::new();
+            router.set_default(&mut default);
+            assert!(router
+                .insert(Route::with_stream(1, 4, 1), &mut stream1)
+                .is_ok());
+            assert!(router
+                .insert(Route::with_stream(1, 4, 2), &mut stream2)
+                .is_ok());
+            assert!(router.insert(Route::new(1, 3), &mut endpoint).is_ok());
+            assert!(router.insert(Route::new(1, 0), &mut slot).is_ok());
+
+            router.dispatch(transfer(0x1010, (1, 4)));
+            router.dispatch(transfer(0x2010, (1, 4)));
+            router.dispatch(transfer(0x2020, (1, 4)));
+            router.dispatch(transfer(0x3000, (1, 3)));
+            router.dispatch(transfer(0x3000, (1, 5)));
+            router.dispatch(transfer(0x3000, (2, 3)));
+            router.dispatch(Allowed::try_from([0, 0, 1 << 24, 1 << 24 | 38 << 10 | 1]).unwrap());
+            router.dispatch(Allowed::try_from([3 << 24, 0, 1 << 24, 34 << 10 | 1]).unwrap());
+
+            router.remove_slot(1);
+            router.dispatch(transfer(0x3000, (1, 3)));
+        }
+
+        assert_eq!(stream1.transfers, 1);
+        assert_eq!(stream2.transfers, 2);
+        assert_eq!(endpoint.transfers, 1);
+        assert_eq!((slot.transfers, slot.notifications), (1, 1));
+        assert_eq!((default.transfers, default.port_status_changes), (2, 1));
+    }
+}
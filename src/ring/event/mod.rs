@@ -0,0 +1,295 @@
+//! Event Ring.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::ring::{event, Segment};
+//!
+//! # let mut memory0 = [[0; 4]; 256];
+//! # let mut memory1 = [[0; 4]; 256];
+//! # let mut table = [event::SegmentTableEntry::default(); 2];
+//! let mut segments = [
+//!     Segment::new(&mut memory0, 0x1_0000),
+//!     Segment::new(&mut memory1, 0x2_0000),
+//! ];
+//! let mut ring = event::Ring::new(&mut segments);
+//! ring.write_segment_table(&mut table);
+//!
+//! // Write `ring.segment_table_size()` to ERSTSZ, the address of `table` to ERSTBA, and call
+//! // `ring.update_erdp` to initialize ERDP.
+//!
+//! for e in &mut ring {
+//!     match e {
+//!         Ok(e) => {
+//!             // Handle the event.
+//!         }
+//!         Err(raw) => {
+//!             // The Event TRB is not supported by this crate.
+//!         }
+//!     }
+//! }
+//! ```
+
+use super::trb::event::Allowed;
+use super::Segment;
+use crate::registers::runtime::EventRingDequeuePointerRegister;
+use bit_field::BitField;
+use core::convert::{TryFrom, TryInto};
+use core::ptr;
+
+pub mod dispatch;
+
+/// Event Ring Segment Table Entry.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SegmentTableEntry([u32; 4]);
+impl SegmentTableEntry {
+    /// Returns the value of the Ring Segment Base Address field.
+    #[must_use]
+    pub fn ring_segment_base_address(self) -> u64 {
+        let l: u64 = self.0[0].into();
+        let u: u64 = self.0[1].into();
+
+        (u << 32) | l
+    }
+
+    /// Sets the value of the Ring Segment Base Address field.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `a` is not 64-byte aligned.
+    pub fn set_ring_segment_base_address(&mut self, a: u64) -> &mut Self {
+        assert!(
+            a.trailing_zeros() >= 6,
+            "The Ring Segment Base Address must be 64-byte aligned."
+        );
+
+        self.0[0] = a.get_bits(0..32).try_into().unwrap();
+        self.0[1] = a.get_bits(32..64).try_into().unwrap();
+        self
+    }
+
+    /// Returns the value of the Ring Segment Size field.
+    #[must_use]
+    pub fn ring_segment_size(self) -> u16 {
+        self.0[2].get_bits(0..=15).try_into().unwrap()
+    }
+
+    /// Sets the value of the Ring Segment Size field.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `s < 16 || s > 4096`.
+    pub fn set_ring_segment_size(&mut self, s: u16) -> &mut Self {
+        assert!(
+            (16..=4096).contains(&s),
+            "The Ring Segment Size must be within 16..=4096."
+        );
+
+        self.0[2].set_bits(0..=15, s.into());
+        self
+    }
+}
+impl AsRef<[u32]> for SegmentTableEntry {
+    fn as_ref(&self) -> &[u32] {
+        &self.0
+    }
+}
+impl_debug_from_methods! {
+    SegmentTableEntry {
+        ring_segment_base_address,
+        ring_segment_size,
+    }
+}
+
+/// Event Ring.
+///
+/// The iterator over this struct yields Event TRBs written by the xHC. It returns [`None`] if
+/// there is no Event TRB to handle. The iteration can be resumed after the xHC writes new events.
+#[derive(Debug)]
+pub struct Ring<'r, 's> {
+    segments: &'r mut [Segment<'s>],
+    segment_index: usize,
+    dequeue: usize,
+    cycle_state: bool,
+}
+impl<'r, 's> Ring<'r, 's> {
+    /// Creates a new Event Ring.
+    ///
+    /// Each element of `segments` corresponds to an entry of the Event Ring Segment Table in the
+    /// same order.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `segments` is empty, or if the number of TRBs in a segment is not
+    /// within `16..=4096`.
+    #[must_use]
+    pub fn new(segments: &'r mut [Segment<'s>]) -> Self {
+        assert!(
+            !segments.is_empty(),
+            "The Event Ring must contain at least one segment."
+        );
+        assert!(
+            segments
+                .iter()
+                .all(|s| (16..=4096).contains(&s.trb_count())),
+            "The number of TRBs in a segment must be within 16..=4096."
+        );
+
+        Self {
+            segments,
+            segment_index: 0,
+            dequeue: 0,
+            cycle_state: true,
+        }
+    }
+
+    /// Returns the number of segments, which is the value written to the Event Ring Segment Table
+    /// Size Register.
+    #[must_use]
+    pub fn segment_table_size(&self) -> u16 {
+        self.segments.len().try_into().unwrap()
+    }
+
+    /// Writes the Event Ring Segment Table describing this ring to `table`.
+    ///
+    /// The address of `table` must be written to the Event Ring Segment Table Base Address
+    /// Register.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the length of `table` is different from the number of segments.
+    pub fn write_segment_table(&self, table: &mut [SegmentTableEntry]) {
+        assert_eq!(
+            table.len(),
+            self.segments.len(),
+            "The number of the entries must be the same as that of the segments."
+        );
+
+        for (e, s) in table.iter_mut().zip(self.segments.iter()) {
+            let mut v = SegmentTableEntry::default();
+            v.set_ring_segment_base_address(s.phys_base())
+                .set_ring_segment_size(s.trb_count().try_into().unwrap());
+
+            // SAFETY: `e` is a valid reference.
+            unsafe { ptr::write_volatile(e, v) };
+        }
+    }
+
+    /// Sets the Event Ring Dequeue Pointer and the Dequeue ERST Segment Index of `erdp` to the
+    /// current dequeue position, and clears the Event Handler Busy bit.
+    ///
+    /// Write the updated value to the Event Ring Dequeue Pointer Register after handling events.
+    pub fn update_erdp(&self, erdp: &mut EventRingDequeuePointerRegister) {
+        let segment = &self.segments[self.segment_index];
+
+        erdp.set_event_ring_dequeue_pointer(segment.phys_addr(self.dequeue));
+        erdp.set_dequeue_erst_segment_index(self.segment_index.get_bits(0..3).try_into().unwrap());
+        erdp.clear_event_handler_busy();
+    }
+
+    fn advance(&mut self) {
+        self.dequeue += 1;
+
+        if self.dequeue == self.segments[self.segment_index].trb_count() {
+            self.dequeue = 0;
+            self.segment_index += 1;
+
+            if self.segment_index == self.segments.len() {
+                self.segment_index = 0;
+                self.cycle_state = !self.cycle_state;
+            }
+        }
+    }
+}
+impl Iterator for Ring<'_, '_> {
+    type Item = Result<Allowed, [u32; 4]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = self.segments[self.segment_index].read(self.dequeue);
+
+        if raw[3].get_bit(0) != self.cycle_state {
+            return None;
+        }
+
+        self.advance();
+
+        Some(Allowed::try_from(raw))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ring::trb::event::{CompletionCode, PortStatusChange};
+
+    fn port_status_change(cycle_bit: bool) -> [u32; 4] {
+        [
+            0x0100_0000,
+            0,
+            0x0100_0000,
+            0x0000_8800 | u32::from(cycle_bit),
+        ]
+    }
+
+    #[test]
+    fn consume_events_across_segments() {
+        let mut memory0 = [[0; 4]; 16];
+        let mut memory1 = [[0; 4]; 16];
+        let mut table = [SegmentTableEntry::default(); 2];
+
+        let mut segments = [
+            Segment::new(&mut memory0, 0x1000),
+            Segment::new(&mut memory1, 0x2000),
+        ];
+        let mut ring = Ring::new(&mut segments);
+        ring.write_segment_table(&mut table);
+
+        assert_eq!(ring.segment_table_size(), 2);
+        assert_eq!(table[1].ring_segment_base_address(), 0x2000);
+        assert_eq!(table[1].ring_segment_size(), 16);
+        assert!(ring.next().is_none());
+
+        for i in 0..16 {
+            ring.segments[0].write(i, port_status_change(true));
+        }
+        ring.segments[1].write(0, port_status_change(true));
+
+        for _ in 0..17 {
+            let e = ring.next().unwrap().unwrap();
+            let Allowed::PortStatusChange(p) = e else {
+                panic!("Unexpected event: {:?}", e);
+            };
+            assert_eq!(p.port_id(), 1);
+            assert_eq!(p.completion_code(), Ok(CompletionCode::Success));
+        }
+        assert!(ring.next().is_none());
+
+        let mut erdp = EventRingDequeuePointerRegister::default();
+        ring.update_erdp(&mut erdp);
+        assert_eq!(erdp.event_ring_dequeue_pointer(), 0x2010);
+        assert_eq!(erdp.dequeue_erst_segment_index(), 1);
+        assert!(erdp.event_handler_busy());
+    }
+
+    #[test]
+    fn cycle_state_toggles_after_last_segment() {
+        let mut memory = [[0; 4]; 16];
+        let mut segments = [Segment::new(&mut memory, 0x1000)];
+        let mut ring = Ring::new(&mut segments);
+
+        for i in 0..16 {
+            ring.segments[0].write(i, port_status_change(true));
+        }
+        assert_eq!(ring.by_ref().count(), 16);
+
+        ring.segments[0].write(0, port_status_change(false));
+        assert_eq!(
+            ring.next(),
+            Some(Ok(Allowed::PortStatusChange(
+                PortStatusChange::try_from(port_status_change(false)).unwrap()
+            )))
+        );
+        assert!(ring.next().is_none());
+    }
+}
@@ -0,0 +1,403 @@
+//! Futures resolved by the events.
+//!
+//! This module does not depend on any executor. Submitting a command or a TD with [`Slots`]
+//! returns a [`Completion`], a [`Future`] which resolves to the event generated for the submitted
+//! TRB. The code which consumes the Event Ring passes each event to [`Slots::wake`], and the
+//! executor is notified through the [`Waker`] of the task.
+//!
+//! [`Slots`] is a table with a fixed number of entries, and one entry is occupied by each
+//! [`Completion`] until it resolves or is dropped. It is not [`Sync`], so the futures must be
+//! polled on the thread which calls [`Slots::wake`]. For example, the interrupt handler wakes a
+//! task which consumes the Event Ring.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::ring::future::Slots;
+//! use xhci::ring::trb::command::EnableSlot;
+//! use xhci::ring::trb::event::{Allowed, CommandCompletion};
+//! use xhci::ring::{command, Segment};
+//!
+//! async fn enable_slot(
+//!     commands: &Slots<CommandCompletion, 8>,
+//!     ring: &mut command::Ring<'_>,
+//! ) -> u8 {
+//!     let completion = commands
+//!         .submit_command(ring, EnableSlot::new().into())
+//!         .expect("No space for the command.");
+//!
+//!     // Ring the Doorbell 0.
+//!
+//!     completion.await.slot_id()
+//! }
+//!
+//! let commands = Slots::<CommandCompletion, 8>::new();
+//! # let mut memory = [[0; 4]; 16];
+//! # let mut ring = command::Ring::new(Segment::new(&mut memory, 0x1000));
+//! # let mut events = core::iter::empty::<Result<Allowed, [u32; 4]>>();
+//!
+//! // In the task which consumes the Event Ring:
+//! for e in &mut events {
+//!     if let Ok(Allowed::CommandCompletion(c)) = e {
+//!         ring.handle_completion(&c);
+//!         let _ = commands.wake(c);
+//!     }
+//! }
+//! ```
+//!
+//! [`Future`]: core::future::Future
+
+use super::trb::event::{CommandCompletion, CompletionCode, TransferEvent};
+use super::{command, transfer, trb, Full};
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+/// An event which completes a submitted TRB.
+pub trait Event: Copy {
+    /// Returns the physical address of the TRB which this event completes.
+    ///
+    /// [`None`] is returned if the event does not complete any TRB.
+    fn completed_trb(&self) -> Option<u64>;
+}
+impl Event for CommandCompletion {
+    /// The Command Ring Stopped event points to the command which is not executed yet, so it does
+    /// not complete any command.
+    fn completed_trb(&self) -> Option<u64> {
+        (self.completion_code() != Ok(CompletionCode::CommandRingStopped))
+            .then(|| self.command_trb_pointer())
+    }
+}
+impl Event for TransferEvent {
+    /// The TRB Pointer field of an event generated by an Event Data TRB holds the Event Data, not
+    /// an address.
+    fn completed_trb(&self) -> Option<u64> {
+        (!self.event_data()).then(|| self.trb_pointer())
+    }
+}
+
+/// A fixed-capacity table of the TRBs waited by [`Completion`]s.
+///
+/// Up to `N` TRBs can be waited at the same time.
+#[derive(Debug)]
+pub struct Slots<T: Event, const N: usize> {
+    entries: RefCell<[Entry<T>; N]>,
+}
+impl<T: Event, const N: usize> Slots<T, N> {
+    /// Creates an empty table.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            entries: RefCell::new([const { Entry::Vacant }; N]),
+        }
+    }
+
+    /// Returns a future which resolves to the event completing the TRB at `address`.
+    ///
+    /// Use this method to wait for a TRB which is enqueued without [`Slots::submit_command`] or
+    /// [`Slots::submit_transfer`].
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if all the entries are occupied.
+    pub fn register(&self, address: u64) -> Result<Completion<'_, T, N>, Full> {
+        let index = self.vacant().ok_or(Full)?;
+        self.entries.borrow_mut()[index] = Entry::Waiting {
+            address,
+            waker: None,
+        };
+
+        Ok(Completion {
+            slots: self,
+            index,
+            address,
+            done: false,
+        })
+    }
+
+    /// Resolves the future waiting for the TRB completed by `event`, and wakes its task.
+    ///
+    /// # Errors
+    ///
+    /// This method returns `event` back if no future waits for it. This happens if the
+    /// [`Completion`] has been dropped, or if the event does not complete any TRB.
+    pub fn wake(&self, event: T) -> Result<(), T> {
+        let Some(address) = event.completed_trb() else {
+            return Err(event);
+        };
+
+        let waker = {
+            let mut entries = self.entries.borrow_mut();
+            let Some(entry) = entries
+                .iter_mut()
+                .find(|e| matches!(e, Entry::Waiting { address: a, .. } if *a == address))
+            else {
+                return Err(event);
+            };
+
+            match core::mem::replace(entry, Entry::Completed(event)) {
+                Entry::Waiting { waker, .. } => waker,
+                _ => unreachable!("Only a waiting entry is replaced."),
+            }
+        };
+
+        // The table is not borrowed here in case the task is polled in `wake`.
+        if let Some(w) = waker {
+            w.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of the occupied entries.
+    #[must_use]
+    pub fn occupied(&self) -> usize {
+        self.entries
+            .borrow()
+            .iter()
+            .filter(|e| !matches!(e, Entry::Vacant))
+            .count()
+    }
+
+    fn vacant(&self) -> Option<usize> {
+        self.entries
+            .borrow()
+            .iter()
+            .position(|e| matches!(e, Entry::Vacant))
+    }
+
+    fn submit(
+        &self,
+        enqueue: impl FnOnce() -> Result<u64, Full>,
+    ) -> Result<Completion<'_, T, N>, Full> {
+        // Check the space first so that a TRB is never enqueued without being waited.
+        if self.vacant().is_none() {
+            return Err(Full);
+        }
+
+        self.register(enqueue()?)
+    }
+}
+impl<const N: usize> Slots<CommandCompletion, N> {
+    /// Enqueues a command and returns a future which resolves to its Command Completion Event.
+    ///
+    /// Note that this method does not ring the Doorbell.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if there is no space for the command in `ring`, or if
+    /// all the entries are occupied. The command is not enqueued in either case.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `command` is a Link TRB.
+    pub fn submit_command(
+        &self,
+        ring: &mut command::Ring<'_>,
+        command: trb::command::Allowed,
+    ) -> Result<Completion<'_, CommandCompletion, N>, Full> {
+        self.submit(|| ring.enqueue(command))
+    }
+}
+impl<const N: usize> Slots<TransferEvent, N> {
+    /// Enqueues a TD and returns a future which resolves to the Transfer Event of its last TRB.
+    ///
+    /// Set the Interrupt On Completion bit of the last TRB, or the future never resolves. If the
+    /// TD ends with a short packet, the xHC generates the event of the last TRB with the Short
+    /// Packet Completion Code. The events of the other TRBs are returned back by
+    /// [`Slots::wake`].
+    ///
+    /// Note that this method does not ring the Doorbell.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if there is no space for the TD in `ring`, or if all the
+    /// entries are occupied. The TD is not enqueued in either case.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `td` is empty or contains a Link TRB.
+    pub fn submit_transfer<const M: usize>(
+        &self,
+        ring: &mut transfer::Ring<'_, M>,
+        td: &[trb::transfer::Allowed],
+    ) -> Result<Completion<'_, TransferEvent, N>, Full> {
+        self.submit(|| ring.enqueue(td))
+    }
+}
+impl<T: Event, const N: usize> Default for Slots<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A future which resolves to the event completing a TRB.
+///
+/// The entry of [`Slots`] is freed when this future resolves or is dropped. The event generated
+/// after dropping it is returned back by [`Slots::wake`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Completion<'a, T: Event, const N: usize> {
+    slots: &'a Slots<T, N>,
+    index: usize,
+    address: u64,
+    // The entry may be used by another future after this one resolves.
+    done: bool,
+}
+impl<T: Event, const N: usize> Completion<'_, T, N> {
+    /// Returns the physical address of the waited TRB.
+    #[must_use]
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+impl<T: Event, const N: usize> Future for Completion<'_, T, N> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(!self.done, "`Completion` is polled after completion.");
+
+        let mut entries = self.slots.entries.borrow_mut();
+        let entry = &mut entries[self.index];
+
+        match entry {
+            Entry::Completed(e) => {
+                let e = *e;
+                *entry = Entry::Vacant;
+                drop(entries);
+
+                self.done = true;
+                Poll::Ready(e)
+            }
+            Entry::Waiting { waker, .. } => {
+                match waker {
+                    Some(w) if w.will_wake(cx.waker()) => {}
+                    _ => *waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+            Entry::Vacant => unreachable!("The entry is freed before the future resolves."),
+        }
+    }
+}
+impl<T: Event, const N: usize> Drop for Completion<'_, T, N> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.slots.entries.borrow_mut()[self.index] = Entry::Vacant;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Entry<T> {
+    Vacant,
+    Waiting { address: u64, waker: Option<Waker> },
+    Completed(T),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ring::trb::command::Noop;
+    use crate::ring::trb::transfer::Normal;
+    use crate::ring::Segment;
+    use core::convert::TryFrom;
+    use core::ptr;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{RawWaker, RawWakerVTable};
+
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(ptr::null(), &VTABLE),
+        |_| {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        },
+        |_| {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        },
+        |_| {},
+    );
+
+    fn poll<F: Future + Unpin>(f: &mut F) -> Poll<F::Output> {
+        let waker = unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) };
+        Pin::new(f).poll(&mut Context::from_waker(&waker))
+    }
+
+    fn completion(addr: u64, code: u32) -> CommandCompletion {
+        CommandCompletion::try_from([u32::try_from(addr).unwrap(), 0, code << 24, 33 << 10 | 1])
+            .unwrap()
+    }
+
+    #[test]
+    fn commands_and_transfers() {
+        let mut command_memory = [[0; 4]; 4];
+        let mut command_ring = command::Ring::new(Segment::new(&mut command_memory, 0x1000));
+        let mut transfer_memory = [[0; 4]; 4];
+        let mut transfer_ring =
+            transfer::Ring::<1>::new(Segment::new(&mut transfer_memory, 0x2000));
+
+        let commands = Slots::<CommandCompletion, 1>::new();
+        let transfers = Slots::<TransferEvent, 2>::new();
+
+        let mut noop = commands
+            .submit_command(&mut command_ring, Noop::new().into())
+            .unwrap();
+        let addr = noop.address();
+        assert!(commands
+            .submit_command(&mut command_ring, Noop::new().into())
+            .is_err());
+        assert!(poll(&mut noop).is_pending());
+
+        // The ring is stopped before the command is executed.
+        assert!(commands.wake(completion(addr, 24)).is_err());
+        assert!(poll(&mut noop).is_pending());
+
+        let wakes = WAKES.load(Ordering::SeqCst);
+        assert_eq!(commands.wake(completion(addr, 1)), Ok(()));
+        assert_eq!(WAKES.load(Ordering::SeqCst), wakes + 1);
+        assert_eq!(
+            poll(&mut noop).map(|c| c.completion_code()),
+            Poll::Ready(Ok(CompletionCode::Success))
+        );
+        drop(noop);
+        assert_eq!(commands.occupied(), 0);
+
+        let mut normal = Normal::new();
+        normal.set_interrupt_on_completion();
+        let transfer = transfers
+            .submit_transfer(&mut transfer_ring, &[normal.into(), normal.into()])
+            .unwrap();
+        let addr = transfer.address();
+        assert_eq!(addr, 0x2010);
+        assert_eq!(transfers.occupied(), 1);
+
+        // A dropped future is not resolved.
+        drop(transfer);
+        let event = TransferEvent::try_from([0x2010, 0, 1 << 24, 1 << 16 | 32 << 10 | 1]).unwrap();
+        assert!(transfers.wake(event).is_err());
+        assert_eq!(transfers.occupied(), 0);
+    }
+
+    #[test]
+    fn resolved_future_does_not_free_reused_entry() {
+        let commands = Slots::<CommandCompletion, 1>::new();
+
+        let mut a = commands.register(0x1000).unwrap();
+        assert_eq!(commands.wake(completion(0x1000, 1)), Ok(()));
+        assert!(poll(&mut a).is_ready());
+
+        // `b` takes the entry `a` used.
+        let mut b = commands.register(0x1010).unwrap();
+        drop(a);
+
+        assert_eq!(commands.occupied(), 1);
+        assert_eq!(b.address(), 0x1010);
+        assert_eq!(commands.wake(completion(0x1010, 1)), Ok(()));
+        assert_eq!(
+            poll(&mut b).map(|c| c.command_trb_pointer()),
+            Poll::Ready(0x1010)
+        );
+    }
+}
@@ -1,3 +1,120 @@
 //! TRB Ring.
 
+use core::convert::TryFrom;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+
+pub mod command;
+pub mod event;
+pub mod future;
+pub mod transfer;
 pub mod trb;
+
+/// A contiguous block of TRBs which is a part of a TRB Ring.
+///
+/// The memory must be accessible by the xHC. The TRBs in a Segment are read and written with
+/// volatile accesses.
+#[derive(Debug)]
+pub struct Segment<'a> {
+    trbs: &'a mut [[u32; 4]],
+    phys_base: u64,
+}
+impl<'a> Segment<'a> {
+    /// Creates a new Segment.
+    ///
+    /// `trbs` is the memory of the Segment, and `phys_base` is the physical address of `trbs[0]`.
+    /// All the TRBs in `trbs` are cleared to 0.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `trbs` is empty, if `phys_base` is not 64-byte aligned, or if the
+    /// Segment crosses a 64KB boundary.
+    pub fn new(trbs: &'a mut [[u32; 4]], phys_base: u64) -> Self {
+        assert!(!trbs.is_empty(), "A Segment must contain at least one TRB.");
+        assert!(
+            phys_base.trailing_zeros() >= 6,
+            "The base address of a Segment must be 64-byte aligned."
+        );
+
+        let bytes = u64::try_from(trbs.len() * trb::BYTES).unwrap();
+        assert_eq!(
+            phys_base >> 16,
+            (phys_base + bytes - 1) >> 16,
+            "A Segment must not cross a 64KB boundary."
+        );
+
+        let mut s = Self { trbs, phys_base };
+        for i in 0..s.trbs.len() {
+            s.write(i, [0; 4]);
+        }
+        s
+    }
+
+    /// Returns the physical address of the first TRB of this Segment.
+    #[must_use]
+    pub fn phys_base(&self) -> u64 {
+        self.phys_base
+    }
+
+    /// Returns the number of TRBs this Segment can contain.
+    #[must_use]
+    pub fn trb_count(&self) -> usize {
+        self.trbs.len()
+    }
+
+    pub(crate) fn phys_addr(&self, i: usize) -> u64 {
+        assert!(i < self.trbs.len(), "Index out of range.");
+
+        self.phys_base + u64::try_from(i * trb::BYTES).unwrap()
+    }
+
+    pub(crate) fn index_of(&self, phys_addr: u64) -> Option<usize> {
+        let offset = phys_addr.checked_sub(self.phys_base)?;
+        let i = usize::try_from(offset).ok()? / trb::BYTES;
+
+        (offset.trailing_zeros() >= 4 && i < self.trbs.len()).then_some(i)
+    }
+
+    /// Reads a TRB.
+    ///
+    /// The last dword, which contains the Cycle bit, is read before the others so that a TRB
+    /// written by the xHC is read only after its ownership is transferred.
+    pub(crate) fn read(&self, i: usize) -> [u32; 4] {
+        let t = &self.trbs[i];
+
+        let mut v = [0; 4];
+
+        // SAFETY: `t[3]` is a valid reference.
+        v[3] = unsafe { ptr::read_volatile(ptr::addr_of!(t[3])) };
+
+        atomic::fence(Ordering::Acquire);
+
+        for (v, d) in v.iter_mut().zip(t.iter()).take(3) {
+            // SAFETY: `d` is a valid reference.
+            *v = unsafe { ptr::read_volatile(d) };
+        }
+        v
+    }
+
+    /// Writes a TRB.
+    ///
+    /// The last dword, which contains the Cycle bit, is written after the others are visible so
+    /// that the xHC never sees a partially written TRB.
+    pub(crate) fn write(&mut self, i: usize, trb: [u32; 4]) {
+        let t = &mut self.trbs[i];
+
+        for (d, v) in t.iter_mut().zip(trb).take(3) {
+            // SAFETY: `d` is a valid reference.
+            unsafe { ptr::write_volatile(d, v) };
+        }
+
+        atomic::fence(Ordering::Release);
+
+        // SAFETY: `t[3]` is a valid reference.
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!(t[3]), trb[3]) };
+    }
+}
+
+/// A struct representing that a Ring has no space for new TRBs.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
+pub struct Full;
@@ -0,0 +1,248 @@
+//! Control transfer.
+
+use crate::ring::trb::transfer::{
+    Allowed, DataStage, Direction, SetupStage, StatusStage, TransferType,
+};
+use crate::usb::request::SetupPacket;
+use bit_field::BitField;
+use core::ops::Deref;
+
+/// A builder of the TRBs of a control transfer.
+///
+/// The builder creates a Setup Stage TRB, a Data Stage TRB if `wLength` is not 0, and a Status
+/// Stage TRB. The fields which depend on the others, such as the Transfer Type field, the
+/// Direction fields, and the Interrupt On Completion bit of the Status Stage TRB, are set
+/// properly.
+///
+/// The Data Stage TRB always points to a data buffer. The Immediate Data bit is never set, even if
+/// an OUT Data Stage is 8 bytes or less.
+///
+/// # Examples
+///
+/// ```no_run
+/// use xhci::ring::transfer::control::ControlTransfer;
+///
+/// // GET_DESCRIPTOR (Device)
+/// let trbs = ControlTransfer::new()
+///     .set_request_type(0x80)
+///     .set_request(6)
+///     .set_value(0x0100)
+///     .set_length(18)
+///     .set_data_buffer_pointer(0x3_0000)
+///     .build();
+///
+/// assert_eq!(trbs.len(), 3);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct ControlTransfer {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    data_buffer_pointer: Option<u64>,
+}
+impl ControlTransfer {
+    /// Creates a new builder. All the fields of the setup packet are set to 0, and no data buffer is
+    /// set.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of the `bmRequestType` field.
+    ///
+    /// Bit 7 of the value decides the direction of the Data Stage.
+    pub fn set_request_type(&mut self, t: u8) -> &mut Self {
+        self.request_type = t;
+        self
+    }
+
+    /// Sets the value of the `bRequest` field.
+    pub fn set_request(&mut self, r: u8) -> &mut Self {
+        self.request = r;
+        self
+    }
+
+    /// Sets the value of the `wValue` field.
+    pub fn set_value(&mut self, v: u16) -> &mut Self {
+        self.value = v;
+        self
+    }
+
+    /// Sets the value of the `wIndex` field.
+    pub fn set_index(&mut self, i: u16) -> &mut Self {
+        self.index = i;
+        self
+    }
+
+    /// Sets the value of the `wLength` field, which is also the length of the Data Stage.
+    pub fn set_length(&mut self, l: u16) -> &mut Self {
+        self.length = l;
+        self
+    }
+
+    /// Sets the physical address of the data buffer.
+    ///
+    /// The buffer must be `wLength` bytes long and must not cross a 64KB boundary. The buffer is
+    /// required even if the Data Stage is small enough to be immediate data.
+    pub fn set_data_buffer_pointer(&mut self, p: u64) -> &mut Self {
+        self.data_buffer_pointer = Some(p);
+        self
+    }
+
+    /// Returns the TRBs of this control transfer.
+    ///
+    /// The Cycle bits and the Chain bits are not set. [`Ring::enqueue`](super::Ring::enqueue)
+    /// sets them.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `wLength` is not 0 but no data buffer is set, if a data buffer is set
+    /// but `wLength` is 0, or if the data buffer crosses a 64KB boundary.
+    #[must_use]
+    pub fn build(&self) -> Trbs {
+        let direction = Direction::from(self.request_type.get_bit(7));
+
+        let mut setup = SetupStage::new();
+        setup
+            .set_request_type(self.request_type)
+            .set_request(self.request)
+            .set_value(self.value)
+            .set_index(self.index)
+            .set_length(self.length)
+            .set_transfer_type(if self.length == 0 {
+                TransferType::No
+            } else if direction == Direction::In {
+                TransferType::In
+            } else {
+                TransferType::Out
+            });
+
+        let mut trbs = Trbs {
+            trbs: [setup.into(); 3],
+            len: 1,
+        };
+
+        if let Some(p) = self.data_buffer_pointer {
+            assert_ne!(
+                self.length, 0,
+                "wLength must not be 0 if a data buffer is set."
+            );
+            assert_eq!(
+                p >> 16,
+                (p + u64::from(self.length) - 1) >> 16,
+                "The data buffer must not cross a 64KB boundary."
+            );
+
+            let mut data = DataStage::new();
+            data.set_data_buffer_pointer(p)
+                .set_trb_transfer_length(self.length.into())
+                .set_direction(direction);
+
+            trbs.push(data.into());
+        } else {
+            assert_eq!(
+                self.length, 0,
+                "A data buffer is required if wLength is not 0."
+            );
+        }
+
+        // The Status Stage is in the opposite direction of the Data Stage, or IN if there is no
+        // Data Stage.
+        let mut status = StatusStage::new();
+        if self.length == 0 || direction == Direction::Out {
+            status.set_direction();
+        }
+        status.set_interrupt_on_completion();
+
+        trbs.push(status.into());
+
+        trbs
+    }
+}
+
+impl From<SetupPacket> for ControlTransfer {
+    /// Creates a builder with the fields of `p`. No data buffer is set.
+    fn from(p: SetupPacket) -> Self {
+        Self {
+            request_type: p.request_type.into(),
+            request: p.request,
+            value: p.value,
+            index: p.index,
+            length: p.length,
+            data_buffer_pointer: None,
+        }
+    }
+}
+
+/// The TRBs of a control transfer.
+///
+/// This struct dereferences to a slice of 2 or 3 TRBs, which can be passed to
+/// [`Ring::enqueue`](super::Ring::enqueue).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Trbs {
+    trbs: [Allowed; 3],
+    len: usize,
+}
+impl Trbs {
+    fn push(&mut self, t: Allowed) {
+        self.trbs[self.len] = t;
+        self.len += 1;
+    }
+}
+impl Deref for Trbs {
+    type Target = [Allowed];
+
+    fn deref(&self) -> &Self::Target {
+        &self.trbs[..self.len]
+    }
+}
+impl AsRef<[Allowed]> for Trbs {
+    fn as_ref(&self) -> &[Allowed] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn control_in_transfer() {
+        let trbs = ControlTransfer::new()
+            .set_request_type(0x80)
+            .set_request(6)
+            .set_value(0x0100)
+            .set_length(18)
+            .set_data_buffer_pointer(0x3000)
+            .build();
+
+        let [Allowed::SetupStage(setup), Allowed::DataStage(data), Allowed::StatusStage(status)] =
+            *trbs
+        else {
+            panic!("Unexpected TRBs: {:?}", trbs);
+        };
+
+        assert_eq!(setup.transfer_type(), TransferType::In);
+        assert_eq!(setup.length(), 18);
+        assert_eq!(data.direction(), Direction::In);
+        assert_eq!(data.trb_transfer_length(), 18);
+        assert_eq!(data.data_buffer_pointer(), 0x3000);
+        assert!(!status.direction());
+        assert!(status.interrupt_on_completion());
+    }
+
+    #[test]
+    fn no_data_stage() {
+        let trbs = ControlTransfer::new().set_request(9).set_value(1).build();
+
+        let [Allowed::SetupStage(setup), Allowed::StatusStage(status)] = *trbs else {
+            panic!("Unexpected TRBs: {:?}", trbs);
+        };
+
+        assert_eq!(setup.transfer_type(), TransferType::No);
+        assert!(status.direction());
+    }
+}
@@ -0,0 +1,452 @@
+//! Isochronous transfer.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::context::{self, DeviceHandler};
+//! use xhci::registers::capability::StructuralParameters2;
+//! use xhci::registers::runtime::MicroframeIndexRegister;
+//! use xhci::ring::transfer::isoch::Scheduler;
+//!
+//! # let device = context::Device::new_32byte();
+//! # let hcsparams2: StructuralParameters2 = unimplemented!();
+//! # let mfindex: MicroframeIndexRegister = unimplemented!();
+//! # let dci = 3;
+//! let mut scheduler = Scheduler::new(device.endpoint(dci), hcsparams2);
+//!
+//! // Queue a TD for each service interval, starting from the earliest one the xHC accepts.
+//! for i in 0..8 {
+//!     match scheduler.schedule(mfindex, (0x3_0000 + i * 0x400, 0x400)) {
+//!         Ok(isoch) => {
+//!             // Enqueue `isoch.into()` to the Transfer Ring.
+//!         }
+//!         // Retry after MFINDEX advances.
+//!         Err(_) => break,
+//!     }
+//! }
+//! ```
+
+use crate::context::EndpointHandler;
+use crate::registers::capability::StructuralParameters2;
+use crate::registers::runtime::MicroframeIndexRegister;
+use crate::ring::trb::event::{CompletionCode, TransferEvent};
+use crate::ring::trb::transfer::Isoch;
+use bit_field::BitField;
+use core::convert::TryInto;
+
+/// The number of microframes MFINDEX counts before it wraps around.
+const MICROFRAMES: u16 = 1 << 14;
+
+/// The number of frames ahead of MFINDEX a Frame ID may point to.
+pub const MAX_FRAMES_AHEAD: u16 = 895;
+
+/// A builder of the Isoch TRB of an isochronous TD.
+///
+/// The TD consists of a single Isoch TRB. The Transfer Burst Count and the Transfer Last Burst
+/// Packet Count fields are calculated from the length of the TD and the Max Packet Size, Max Burst
+/// Size, and Mult of the endpoint as described in section 4.11.2.3 of the xHCI specification.
+///
+/// # Examples
+///
+/// ```no_run
+/// use xhci::ring::transfer::isoch::IsochTransfer;
+///
+/// // A SuperSpeed endpoint with up to 3 bursts of 4 packets of 1024 bytes.
+/// let isoch = IsochTransfer::new()
+///     .set_max_packet_size(1024)
+///     .set_max_burst_size(3)
+///     .set_mult(2)
+///     .set_data_buffer_pointer(0x3_0000)
+///     .set_length(9 * 1024 + 100)
+///     .set_frame_id(42)
+///     .build();
+///
+/// assert_eq!(isoch.transfer_burst_count(), 2);
+/// assert_eq!(isoch.transfer_last_burst_packet_count(), 1);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct IsochTransfer {
+    max_packet_size: u16,
+    max_burst_size: u8,
+    mult: u8,
+    data_buffer_pointer: u64,
+    length: u32,
+    frame_id: Option<u16>,
+}
+impl IsochTransfer {
+    /// Creates a new builder.
+    ///
+    /// The TD is empty and is scheduled as soon as possible.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new builder with the Max Packet Size, the Max Burst Size, and the Mult of `cx`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the Mult field of `cx` is 3, which is reserved.
+    #[must_use]
+    pub fn for_endpoint(cx: &dyn EndpointHandler) -> Self {
+        let mut t = Self::new();
+        t.set_max_packet_size(cx.max_packet_size())
+            .set_max_burst_size(cx.max_burst_size())
+            .set_mult(cx.mult());
+        t
+    }
+
+    /// Sets the Max Packet Size of the endpoint.
+    pub fn set_max_packet_size(&mut self, s: u16) -> &mut Self {
+        self.max_packet_size = s;
+        self
+    }
+
+    /// Sets the Max Burst Size of the endpoint, which is the number of packets in a burst minus 1.
+    pub fn set_max_burst_size(&mut self, s: u8) -> &mut Self {
+        self.max_burst_size = s;
+        self
+    }
+
+    /// Sets the Mult of the endpoint, which is the maximum number of bursts in a service interval
+    /// minus 1.
+    ///
+    /// The Mult is 0 unless the endpoint is a `SuperSpeed` one.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `m > 2`.
+    pub fn set_mult(&mut self, m: u8) -> &mut Self {
+        assert!(m <= 2, "The Mult must be less than or equal to 2.");
+
+        self.mult = m;
+        self
+    }
+
+    /// Sets the physical address of the data buffer.
+    ///
+    /// The buffer must be as long as the TD and must not cross a 64KB boundary.
+    pub fn set_data_buffer_pointer(&mut self, p: u64) -> &mut Self {
+        self.data_buffer_pointer = p;
+        self
+    }
+
+    /// Sets the length of the TD.
+    pub fn set_length(&mut self, l: u32) -> &mut Self {
+        self.length = l;
+        self
+    }
+
+    /// Sets the Frame ID, the frame in which the TD is transferred.
+    ///
+    /// Only the lower 11 bits are used.
+    pub fn set_frame_id(&mut self, id: u16) -> &mut Self {
+        self.frame_id = Some(id & 0x7ff);
+        self
+    }
+
+    /// Makes the xHC transfer the TD as soon as possible instead of in the frame of the Frame ID.
+    pub fn set_start_isoch_asap(&mut self) -> &mut Self {
+        self.frame_id = None;
+        self
+    }
+
+    /// Returns the Isoch TRB of this TD.
+    ///
+    /// The Interrupt On Completion and Interrupt on Short Packet bits are set. The Cycle bit and
+    /// the Chain bit are not set. [`Ring::enqueue`](super::Ring::enqueue) sets them.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the Max Packet Size is 0, if the data buffer crosses a 64KB
+    /// boundary, or if the TD needs more bursts than the Mult allows.
+    #[must_use]
+    pub fn build(&self) -> Isoch {
+        assert_ne!(
+            self.max_packet_size, 0,
+            "The Max Packet Size must not be 0."
+        );
+        if self.length > 0 {
+            assert_eq!(
+                self.data_buffer_pointer >> 16,
+                (self.data_buffer_pointer + u64::from(self.length) - 1) >> 16,
+                "The data buffer must not cross a 64KB boundary."
+            );
+        }
+
+        // A zero-length TD still transfers a packet.
+        let packets = self.length.div_ceil(self.max_packet_size.into()).max(1);
+        let burst = u32::from(self.max_burst_size) + 1;
+        let bursts = packets.div_ceil(burst);
+        assert!(
+            bursts <= u32::from(self.mult) + 1,
+            "A TD must not need more than Mult + 1 bursts."
+        );
+
+        let last_burst_packets = match packets % burst {
+            0 => burst,
+            n => n,
+        };
+
+        let mut isoch = Isoch::new();
+        isoch
+            .set_data_buffer_pointer(self.data_buffer_pointer)
+            .set_trb_transfer_length(self.length)
+            .set_transfer_burst_count((bursts - 1).try_into().unwrap())
+            .set_transfer_last_burst_packet_count((last_burst_packets - 1).try_into().unwrap())
+            .set_interrupt_on_short_packet()
+            .set_interrupt_on_completion();
+
+        match self.frame_id {
+            Some(id) => isoch.set_frame_id(id),
+            None => isoch.set_start_isoch_asap(),
+        };
+
+        isoch
+    }
+}
+
+/// A scheduler of the isochronous TDs of an endpoint.
+///
+/// The scheduler builds a TD for each service interval of the endpoint. The Frame ID of the first
+/// TD is the frame of the earliest service interval which starts after the Isochronous Scheduling
+/// Threshold from the current MFINDEX, and those of the following TDs are of the consecutive
+/// service intervals. If software falls behind, the schedule restarts from the earliest service
+/// interval again.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Scheduler {
+    transfer: IsochTransfer,
+    interval: u8,
+    threshold: u16,
+    next: Option<u16>,
+}
+impl Scheduler {
+    /// Creates a new scheduler of the endpoint whose Endpoint Context is `cx`.
+    ///
+    /// The Max Packet Size, Max Burst Size, and Interval fields of `cx` must be set.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the service interval is longer than 1024 ms.
+    #[must_use]
+    pub fn new(cx: &dyn EndpointHandler, hcsparams2: StructuralParameters2) -> Self {
+        let interval = cx.interval();
+        assert!(
+            interval <= 13,
+            "The service interval must not be longer than 1024 ms."
+        );
+
+        // If bit 3 of IST is set, the threshold is in frames instead of microframes.
+        let ist = hcsparams2.isochronous_scheduling_threshold();
+        let threshold = u16::from(ist.get_bits(0..=2)) * if ist.get_bit(3) { 8 } else { 1 };
+
+        Self {
+            transfer: IsochTransfer::for_endpoint(cx),
+            interval,
+            threshold,
+            next: None,
+        }
+    }
+
+    /// Returns the length of a service interval in microframes.
+    #[must_use]
+    pub fn service_interval(&self) -> u16 {
+        1 << self.interval
+    }
+
+    /// Returns the Isoch TRB of the TD for the next service interval.
+    ///
+    /// `td` is the physical address of the data buffer and the length of the TD.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`TooFarAhead`] if the service interval starts more than
+    /// [`MAX_FRAMES_AHEAD`] frames after `mfindex`. Retry after MFINDEX advances.
+    pub fn schedule(
+        &mut self,
+        mfindex: MicroframeIndexRegister,
+        (data_buffer_pointer, length): (u64, u32),
+    ) -> Result<Isoch, TooFarAhead> {
+        let now = mfindex.microframe_index();
+        let ahead = |t: u16| t.wrapping_sub(now) % MICROFRAMES;
+
+        let target = match self.next {
+            // The service interval has not passed, and the xHC can still schedule it.
+            Some(t) if ahead(t) > self.threshold && ahead(t) < MICROFRAMES / 2 => t,
+            _ => self.earliest(now),
+        };
+
+        if ahead(target) > MAX_FRAMES_AHEAD * 8 {
+            return Err(TooFarAhead);
+        }
+
+        self.next = Some((target + self.service_interval()) % MICROFRAMES);
+
+        Ok(self
+            .transfer
+            .set_data_buffer_pointer(data_buffer_pointer)
+            .set_length(length)
+            .set_frame_id(target >> 3)
+            .build())
+    }
+
+    /// Returns the result of the TD reported by `e`.
+    ///
+    /// The schedule restarts from the earliest service interval after a Ring Underrun or a Ring
+    /// Overrun, as the xHC stops servicing the endpoint until a new TD is enqueued.
+    pub fn handle_transfer_event(&mut self, e: &TransferEvent) -> Status {
+        match e.completion_code() {
+            Ok(CompletionCode::Success | CompletionCode::ShortPacket) => Status::Transferred {
+                residual: e.trb_transfer_length(),
+            },
+            Ok(CompletionCode::MissedServiceError) => Status::MissedService,
+            Ok(CompletionCode::RingUnderrun) => {
+                self.next = None;
+                Status::RingUnderrun
+            }
+            Ok(CompletionCode::RingOverrun) => {
+                self.next = None;
+                Status::RingOverrun
+            }
+            code => Status::Failed(code),
+        }
+    }
+
+    fn earliest(&self, now: u16) -> u16 {
+        let t = now + self.threshold + 1;
+        let aligned = t.div_ceil(self.service_interval()) * self.service_interval();
+
+        aligned % MICROFRAMES
+    }
+}
+
+/// The result of an isochronous TD.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Status {
+    /// The TD is transferred.
+    Transferred {
+        /// The number of bytes of the TD which are not transferred.
+        residual: u32,
+    },
+    /// The xHC could not service the TD in its service interval, and skipped it.
+    MissedService,
+    /// The xHC had no TD to transmit in a service interval of an OUT endpoint.
+    RingUnderrun,
+    /// The xHC had no TD to receive the data in a service interval of an IN endpoint.
+    RingOverrun,
+    /// The TD completed with an error.
+    ///
+    /// The value is that of the Completion Code field of the Transfer Event TRB.
+    Failed(Result<CompletionCode, u8>),
+}
+
+/// A struct representing that a service interval is too far ahead of MFINDEX to schedule.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
+pub struct TooFarAhead;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::Endpoint32Byte;
+    use core::convert::TryFrom;
+
+    fn mfindex(v: u16) -> MicroframeIndexRegister {
+        // SAFETY: The register consists of a single `u32`.
+        unsafe { core::mem::transmute(u32::from(v)) }
+    }
+
+    fn hcsparams2(ist: u32) -> StructuralParameters2 {
+        // SAFETY: The register consists of a single `u32`.
+        unsafe { core::mem::transmute(ist) }
+    }
+
+    fn transfer_event(code: u32) -> TransferEvent {
+        TransferEvent::try_from([0, 0, code << 24 | 0xa, 0x0101_8001]).unwrap()
+    }
+
+    #[test]
+    fn burst_counts() {
+        let mut t = IsochTransfer::new();
+        t.set_max_packet_size(1024)
+            .set_max_burst_size(3)
+            .set_mult(2);
+
+        for (length, tbc, tlbpc) in [(0, 0, 0), (4096, 0, 3), (4097, 1, 0), (9316, 2, 1)] {
+            let isoch = t.set_length(length).build();
+
+            assert_eq!(isoch.transfer_burst_count(), tbc);
+            assert_eq!(isoch.transfer_last_burst_packet_count(), tlbpc);
+            assert!(isoch.start_isoch_asap());
+        }
+    }
+
+    #[test]
+    fn single_burst_without_mult() {
+        let mut cx = Endpoint32Byte::new_32byte();
+        cx.set_max_packet_size(1024);
+        cx.set_max_burst_size(3);
+
+        let mut t = IsochTransfer::for_endpoint(&cx);
+        let isoch = t.set_length(3000).build();
+        assert_eq!(isoch.transfer_burst_count(), 0);
+        assert_eq!(isoch.transfer_last_burst_packet_count(), 2);
+
+        cx.set_mult(1);
+        let isoch = IsochTransfer::for_endpoint(&cx).set_length(4097).build();
+        assert_eq!(isoch.transfer_burst_count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "A TD must not need more than Mult + 1 bursts.")]
+    fn second_burst_without_mult() {
+        let mut cx = Endpoint32Byte::new_32byte();
+        cx.set_max_packet_size(1024);
+        cx.set_max_burst_size(3);
+
+        // A second burst exceeds the bandwidth reserved for the endpoint.
+        let _ = IsochTransfer::for_endpoint(&cx).set_length(4097).build();
+    }
+
+    #[test]
+    fn schedule_service_intervals() {
+        let mut cx = Endpoint32Byte::new_32byte();
+        cx.set_interval(3);
+        cx.set_max_packet_size(1024);
+
+        // The threshold is a frame.
+        let mut s = Scheduler::new(&cx, hcsparams2(0b1001));
+        let schedule = |now, s: &mut Scheduler| s.schedule(mfindex(now), (0x1000, 1024));
+
+        assert_eq!(schedule(100, &mut s).unwrap().frame_id(), 14);
+        assert_eq!(schedule(100, &mut s).unwrap().frame_id(), 15);
+
+        // Software fell behind.
+        assert_eq!(schedule(200, &mut s).unwrap().frame_id(), 27);
+
+        // MFINDEX wraps around.
+        let mut s = Scheduler::new(&cx, hcsparams2(0b1001));
+        assert_eq!(schedule(16370, &mut s).unwrap().frame_id(), 0);
+        assert_eq!(schedule(16370, &mut s).unwrap().frame_id(), 1);
+
+        let scheduled = (0..1000)
+            .take_while(|_| schedule(16370, &mut s).is_ok())
+            .count();
+        assert_eq!(scheduled, 892);
+        assert_eq!(schedule(16370, &mut s), Err(TooFarAhead));
+
+        assert_eq!(
+            s.handle_transfer_event(&transfer_event(13)),
+            Status::Transferred { residual: 10 }
+        );
+        assert_eq!(
+            s.handle_transfer_event(&transfer_event(23)),
+            Status::MissedService
+        );
+        assert_eq!(
+            s.handle_transfer_event(&transfer_event(14)),
+            Status::RingUnderrun
+        );
+        assert_eq!(schedule(16370, &mut s).unwrap().frame_id(), 0);
+    }
+}
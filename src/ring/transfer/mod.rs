@@ -0,0 +1,448 @@
+//! Transfer Ring.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::ring::{transfer, trb, Segment};
+//!
+//! # let mut memory = [[0; 4]; 256];
+//! # let mut more_memory = [[0; 4]; 256];
+//! let mut ring = transfer::Ring::<4>::new(Segment::new(&mut memory, 0x1_0000));
+//!
+//! let mut normal = trb::transfer::Normal::new();
+//! normal
+//!     .set_data_buffer_pointer(0x3_0000)
+//!     .set_trb_transfer_length(512)
+//!     .set_interrupt_on_completion();
+//!
+//! if ring.enqueue(&[normal.into()]).is_err() {
+//!     ring.add_segment(Segment::new(&mut more_memory, 0x2_0000))
+//!         .expect("Failed to add a segment.");
+//!     ring.enqueue(&[normal.into()]).unwrap();
+//! }
+//! ```
+
+use super::trb::event::{CompletionCode, TransferEvent};
+use super::trb::transfer::Allowed;
+use super::trb::Link;
+use super::{Full, Segment};
+use crate::context::{EndpointHandler, StreamHandler};
+use bit_field::BitField;
+
+pub mod control;
+pub mod isoch;
+pub mod normal;
+
+/// Transfer Ring.
+///
+/// The ring consists of up to `N` [`Segment`]s. The last TRB of each Segment is reserved for a
+/// Link TRB which points to the next Segment.
+#[derive(Debug)]
+pub struct Ring<'a, const N: usize> {
+    segments: [Option<Segment<'a>>; N],
+    num_segments: usize,
+    enqueue: Position,
+    dequeue: Position,
+    cycle_state: bool,
+}
+impl<'a, const N: usize> Ring<'a, N> {
+    /// Creates a new Transfer Ring with a segment.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `N == 0`, or if `segment` contains less than 2 TRBs.
+    #[must_use]
+    pub fn new(segment: Segment<'a>) -> Self {
+        assert_ne!(N, 0, "The Transfer Ring must be able to hold a segment.");
+        Self::assert_segment_len(&segment);
+
+        let mut segments = core::array::from_fn(|_| None);
+        segments[0] = Some(segment);
+
+        Self {
+            segments,
+            num_segments: 1,
+            enqueue: Position::default(),
+            dequeue: Position::default(),
+            cycle_state: true,
+        }
+    }
+
+    /// Enqueues a Transfer Descriptor and returns the physical address of its last TRB.
+    ///
+    /// The Cycle bits of the TRBs are overwritten with the Producer Cycle State, but the Cycle bit
+    /// of the first TRB is written at the end so that the xHC never sees a partially written
+    /// TD. The Chain bit is set on every TRB which is followed by a TRB of the same TD, and is
+    /// cleared on the others. A Setup Stage, Data Stage, Status Stage, or Isoch TRB starts a new
+    /// TD, so a whole Control transfer can be passed at once.
+    ///
+    /// Note that this method does not ring the Doorbell.
+    ///
+    /// # Errors
+    ///
+    /// This method returns a [`Full`] value if there is no space for the TD. Call
+    /// [`Ring::handle_transfer_event`] to free the TRBs consumed by the xHC, or
+    /// [`Ring::add_segment`] to enlarge the ring.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `td` is empty or contains a Link TRB.
+    pub fn enqueue(&mut self, td: &[Allowed]) -> Result<u64, Full> {
+        assert!(!td.is_empty(), "A TD must contain at least one TRB.");
+        assert!(
+            td.iter().all(|t| !matches!(t, Allowed::Link(_))),
+            "The Link TRBs are managed by the Transfer Ring."
+        );
+
+        let mut p = self.enqueue;
+        for _ in td {
+            p = self.next(p);
+            if p == self.dequeue {
+                return Err(Full);
+            }
+        }
+
+        let first = self.enqueue;
+        let first_cycle_state = self.cycle_state;
+        let mut last_addr = 0;
+
+        for (i, trb) in td.iter().enumerate() {
+            let mut trb = *trb;
+            let chain = td.get(i + 1).is_some_and(|t| !starts_td(t));
+            set_chain_bit(&mut trb, chain);
+
+            // The xHC must not see the first TRB until the whole TD is written.
+            set_cycle_bit(&mut trb, (i == 0) != self.cycle_state);
+
+            let Position { segment, index } = self.enqueue;
+            let segment = self.segment_mut(segment);
+            last_addr = segment.phys_addr(index);
+            segment.write(index, trb.into_raw());
+
+            self.advance_enqueue(chain);
+        }
+
+        let segment = self.segment_mut(first.segment);
+        let mut trb = segment.read(first.index);
+        trb[3].set_bit(0, first_cycle_state);
+        segment.write(first.index, trb);
+
+        Ok(last_addr)
+    }
+
+    /// Frees the TRBs which the xHC has consumed.
+    ///
+    /// Call this method with every Transfer Event generated for this ring. Events whose TRB
+    /// Pointer does not point to this ring, and those generated by Event Data TRBs are ignored.
+    pub fn handle_transfer_event(&mut self, e: &TransferEvent) {
+        if e.event_data() {
+            return;
+        }
+
+        if let Some(p) = self.position_of(e.trb_pointer()) {
+            // The TRB Pointer of the Stopped events points to the TRB which was being executed.
+            let stopped = matches!(
+                e.completion_code(),
+                Ok(CompletionCode::Stopped
+                    | CompletionCode::StoppedLengthInvalid
+                    | CompletionCode::StoppedShortPacket)
+            );
+
+            self.dequeue = if stopped { p } else { self.next(p) };
+        }
+    }
+
+    /// Adds a segment to this ring.
+    ///
+    /// The segment is inserted right after the segment containing the Enqueue Pointer, and the Link
+    /// TRB of that segment is rewritten to point to the new one.
+    ///
+    /// # Errors
+    ///
+    /// This method returns `segment` back if the ring already has `N` segments, or if the xHC has
+    /// not consumed the TRBs after the Enqueue Pointer in the same segment. In the latter case,
+    /// retry after the xHC consumes them.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `segment` contains less than 2 TRBs.
+    pub fn add_segment(&mut self, segment: Segment<'a>) -> Result<(), Segment<'a>> {
+        Self::assert_segment_len(&segment);
+
+        let e = self.enqueue;
+        let d = self.dequeue;
+        if self.num_segments == N || (d.segment == e.segment && d.index > e.index) {
+            return Err(segment);
+        }
+
+        // The xHC has not consumed the TRBs in the new segment yet, and the Link TRB of the
+        // Enqueue segment has not been written in this lap.
+        let not_owned = !self.cycle_state;
+
+        self.segments[e.segment + 1..=self.num_segments].rotate_right(1);
+        self.segments[e.segment + 1] = Some(segment);
+        self.num_segments += 1;
+
+        if d.segment > e.segment {
+            self.dequeue.segment += 1;
+        }
+
+        let new = self.segment_mut(e.segment + 1);
+        for i in 0..new.trb_count() {
+            let mut t = [0; 4];
+            t[3].set_bit(0, not_owned);
+            new.write(i, t);
+        }
+
+        for i in [e.segment, e.segment + 1] {
+            let mut l = self.link_trb(i);
+            if not_owned {
+                l.set_cycle_bit();
+            }
+            self.write_link(i, l);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether `address` is the physical address of a TRB of this ring.
+    ///
+    /// The Link TRBs are not regarded as the TRBs of this ring.
+    #[must_use]
+    pub fn contains(&self, address: u64) -> bool {
+        self.position_of(address).is_some()
+    }
+
+    /// Sets the TR Dequeue Pointer and the Dequeue Cycle State of `ep` to the start of this ring.
+    ///
+    /// This method must be called before the xHC starts processing this ring.
+    pub fn initialize_endpoint_context(&self, ep: &mut dyn EndpointHandler) {
+        ep.set_tr_dequeue_pointer(self.segment(0).phys_base());
+        ep.set_dequeue_cycle_state();
+    }
+
+    /// Sets the TR Dequeue Pointer and the Dequeue Cycle State of `s` to the start of this ring.
+    ///
+    /// Use this method instead of [`Ring::initialize_endpoint_context`] if the ring is used for a
+    /// Stream.
+    pub fn initialize_stream_context(&self, s: &mut dyn StreamHandler) {
+        s.set_tr_dequeue_pointer(self.segment(0).phys_base());
+        s.set_dequeue_cycle_state();
+    }
+
+    fn advance_enqueue(&mut self, chain: bool) {
+        self.enqueue.index += 1;
+
+        if self.enqueue.index == self.link_index(self.enqueue.segment) {
+            let mut l = self.link_trb(self.enqueue.segment);
+            if chain {
+                l.set_chain_bit();
+            }
+            if self.cycle_state {
+                l.set_cycle_bit();
+            }
+            self.write_link(self.enqueue.segment, l);
+
+            if self.is_last(self.enqueue.segment) {
+                self.cycle_state = !self.cycle_state;
+            }
+
+            self.enqueue = self.next_segment(self.enqueue);
+        }
+    }
+
+    /// Returns a Link TRB which points to the segment following `segment`.
+    ///
+    /// The Chain and Cycle bits are cleared.
+    fn link_trb(&self, segment: usize) -> Link {
+        let next = self.segment((segment + 1) % self.num_segments).phys_base();
+
+        let mut l = Link::new();
+        l.set_ring_segment_pointer(next);
+
+        if self.is_last(segment) {
+            l.set_toggle_cycle();
+        }
+
+        l
+    }
+
+    fn write_link(&mut self, segment: usize, l: Link) {
+        let index = self.link_index(segment);
+        self.segment_mut(segment).write(index, l.into_raw());
+    }
+
+    fn next(&self, p: Position) -> Position {
+        if p.index + 1 == self.link_index(p.segment) {
+            self.next_segment(p)
+        } else {
+            Position {
+                index: p.index + 1,
+                ..p
+            }
+        }
+    }
+
+    fn next_segment(&self, p: Position) -> Position {
+        Position {
+            segment: (p.segment + 1) % self.num_segments,
+            index: 0,
+        }
+    }
+
+    fn position_of(&self, addr: u64) -> Option<Position> {
+        (0..self.num_segments).find_map(|segment| {
+            let index = self.segment(segment).index_of(addr)?;
+            (index != self.link_index(segment)).then_some(Position { segment, index })
+        })
+    }
+
+    fn is_last(&self, segment: usize) -> bool {
+        segment + 1 == self.num_segments
+    }
+
+    fn link_index(&self, segment: usize) -> usize {
+        self.segment(segment).trb_count() - 1
+    }
+
+    fn segment(&self, i: usize) -> &Segment<'a> {
+        self.segments[i].as_ref().unwrap()
+    }
+
+    fn segment_mut(&mut self, i: usize) -> &mut Segment<'a> {
+        self.segments[i].as_mut().unwrap()
+    }
+
+    fn assert_segment_len(segment: &Segment<'_>) {
+        assert!(
+            segment.trb_count() >= 2,
+            "A segment of the Transfer Ring must contain at least 2 TRBs."
+        );
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+struct Position {
+    segment: usize,
+    index: usize,
+}
+
+fn starts_td(trb: &Allowed) -> bool {
+    matches!(
+        trb,
+        Allowed::SetupStage(_)
+            | Allowed::DataStage(_)
+            | Allowed::StatusStage(_)
+            | Allowed::Isoch(_)
+    )
+}
+
+fn set_chain_bit(trb: &mut Allowed, chain: bool) {
+    macro_rules! arm {
+        ($($variant:ident),*) => {
+            match trb {
+                $(Allowed::$variant(ref mut x) => {
+                    if chain {
+                        x.set_chain_bit();
+                    } else {
+                        x.clear_chain_bit();
+                    }
+                },)*
+                Allowed::SetupStage(_) => {}
+            }
+        };
+    }
+
+    arm!(Normal, DataStage, StatusStage, Isoch, Link, EventData, Noop);
+}
+
+fn set_cycle_bit(trb: &mut Allowed, cycle_bit: bool) {
+    if cycle_bit {
+        trb.set_cycle_bit();
+    } else {
+        trb.clear_cycle_bit();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ring::trb::transfer::{DataStage, Normal, SetupStage, StatusStage};
+    use core::convert::TryFrom;
+
+    fn transfer_event(addr: u64) -> TransferEvent {
+        TransferEvent::try_from([u32::try_from(addr).unwrap(), 0, 0x0100_0000, 0x0101_8001])
+            .unwrap()
+    }
+
+    #[test]
+    fn td_straddling_link_trb() {
+        let mut memory = [[0; 4]; 4];
+        let normal = Normal::new().into();
+
+        {
+            let mut ring = Ring::<1>::new(Segment::new(&mut memory, 0x1000));
+
+            assert_eq!(ring.enqueue(&[normal; 2]), Ok(0x1010));
+            ring.handle_transfer_event(&transfer_event(0x1010));
+            assert_eq!(ring.enqueue(&[normal; 3]), Err(Full));
+            assert_eq!(ring.enqueue(&[normal; 2]), Ok(0x1000));
+        }
+
+        let n2 = Normal::try_from(memory[2]).unwrap();
+        let link = Link::try_from(memory[3]).unwrap();
+        let n0 = Normal::try_from(memory[0]).unwrap();
+
+        assert!(n2.chain_bit() && n2.cycle_bit());
+        assert!(link.chain_bit() && link.toggle_cycle() && link.cycle_bit());
+        assert!(!n0.chain_bit() && !n0.cycle_bit());
+    }
+
+    #[test]
+    fn control_transfer_is_not_chained() {
+        let mut memory = [[0; 4]; 8];
+
+        {
+            let mut ring = Ring::<1>::new(Segment::new(&mut memory, 0x1000));
+            let td = [
+                SetupStage::new().into(),
+                DataStage::new().into(),
+                Normal::new().into(),
+                StatusStage::new().into(),
+            ];
+
+            assert_eq!(ring.enqueue(&td), Ok(0x1030));
+        }
+
+        assert!(DataStage::try_from(memory[1]).unwrap().chain_bit());
+        assert!(!Normal::try_from(memory[2]).unwrap().chain_bit());
+        assert!(!StatusStage::try_from(memory[3]).unwrap().chain_bit());
+        assert!(memory[..4].iter().all(|t| t[3].get_bit(0)));
+    }
+
+    #[test]
+    fn add_segment_when_full() {
+        let mut memory0 = [[0; 4]; 4];
+        let mut memory1 = [[0; 4]; 4];
+        let normal = Normal::new().into();
+
+        {
+            let mut ring = Ring::<2>::new(Segment::new(&mut memory0, 0x1000));
+
+            assert_eq!(ring.enqueue(&[normal; 2]), Ok(0x1010));
+            assert_eq!(ring.enqueue(&[normal]), Err(Full));
+
+            ring.add_segment(Segment::new(&mut memory1, 0x2000))
+                .unwrap();
+            assert_eq!(ring.enqueue(&[normal; 3]), Ok(0x2010));
+        }
+
+        let link0 = Link::try_from(memory0[3]).unwrap();
+        assert_eq!(link0.ring_segment_pointer(), 0x2000);
+        assert!(!link0.toggle_cycle() && link0.cycle_bit() && link0.chain_bit());
+
+        let link1 = Link::try_from(memory1[3]).unwrap();
+        assert_eq!(link1.ring_segment_pointer(), 0x1000);
+        assert!(link1.toggle_cycle() && !link1.cycle_bit());
+    }
+}
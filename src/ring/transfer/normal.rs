@@ -0,0 +1,294 @@
+//! Bulk and Interrupt transfer.
+
+use crate::ring::trb::transfer::{Allowed, Normal};
+use core::cmp;
+use core::convert::TryInto;
+use core::ops::Deref;
+
+/// The maximum number of bytes a TRB can transfer.
+const MAX_TRB_LENGTH: u64 = 0x1_0000;
+
+/// The maximum value of the TD Size field.
+const MAX_TD_SIZE: u64 = 31;
+
+/// A builder of the Normal TRBs of a Bulk or Interrupt TD.
+///
+/// The data buffer of the TD consists of segments of physical memory. A segment is split into
+/// multiple TRBs where it crosses a 64KB boundary. The TD Size field of each TRB is the number of
+/// packets remaining after the TRB as described in section 4.11.2.4 of the xHCI specification.
+///
+/// # Examples
+///
+/// ```no_run
+/// use xhci::ring::transfer::normal::NormalTransfer;
+///
+/// // 8KB of data starting 4KB before a 64KB boundary.
+/// let trbs = NormalTransfer::new()
+///     .set_max_packet_size(512)
+///     .set_segments(&[(0x2_f000, 0x1000), (0x5_0000, 0x1000)])
+///     .build::<4>()
+///     .unwrap();
+///
+/// assert_eq!(trbs.len(), 2);
+/// ```
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct NormalTransfer<'a> {
+    max_packet_size: u16,
+    segments: &'a [(u64, u32)],
+    immediate_data: Option<([u8; 8], u8)>,
+    interrupt_on_short_packet: bool,
+}
+impl<'a> NormalTransfer<'a> {
+    /// Creates a new builder.
+    ///
+    /// The TD has no data.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the Max Packet Size of the endpoint.
+    pub fn set_max_packet_size(&mut self, s: u16) -> &mut Self {
+        self.max_packet_size = s;
+        self
+    }
+
+    /// Sets the segments of the data buffer.
+    ///
+    /// Each element is the physical address and the length of a segment. Empty segments are
+    /// ignored. This method removes the data set by [`NormalTransfer::set_immediate_data`].
+    pub fn set_segments(&mut self, segments: &'a [(u64, u32)]) -> &mut Self {
+        self.segments = segments;
+        self.immediate_data = None;
+        self
+    }
+
+    /// Sets the data which is written to the TRB instead of a data buffer.
+    ///
+    /// The Immediate Data can be used only for OUT endpoints. This method removes the segments set
+    /// by [`NormalTransfer::set_segments`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `data` is longer than 8 bytes.
+    pub fn set_immediate_data(&mut self, data: &[u8]) -> &mut Self {
+        assert!(
+            data.len() <= 8,
+            "The Immediate Data must not be longer than 8 bytes."
+        );
+
+        let mut d = [0; 8];
+        d[..data.len()].copy_from_slice(data);
+
+        self.segments = &[];
+        self.immediate_data = Some((d, data.len().try_into().unwrap()));
+        self
+    }
+
+    /// Sets the Interrupt-on Short Packet bit of all the TRBs.
+    ///
+    /// Set this bit for IN endpoints to be notified when the TD ends with a short packet.
+    pub fn set_interrupt_on_short_packet(&mut self) -> &mut Self {
+        self.interrupt_on_short_packet = true;
+        self
+    }
+
+    /// Returns the Normal TRBs of this TD.
+    ///
+    /// `N` is the maximum number of the TRBs. The Chain bit is set on all the TRBs but the last,
+    /// and the Interrupt On Completion bit is set on the last one. A TD without data consists of a
+    /// single zero-length TRB.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`TooManyTrbs`] if the TD needs more than `N` TRBs.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the Max Packet Size is 0.
+    pub fn build<const N: usize>(&self) -> Result<Trbs<N>, TooManyTrbs> {
+        assert_ne!(
+            self.max_packet_size, 0,
+            "The Max Packet Size must not be 0."
+        );
+
+        let mut trbs = Trbs::new();
+
+        if let Some((data, len)) = self.immediate_data {
+            let mut n = Normal::new();
+            n.set_data_buffer_pointer(u64::from_le_bytes(data))
+                .set_trb_transfer_length(len.into())
+                .set_immediate_data();
+            trbs.push(n)?;
+        } else {
+            let total: u64 = self.segments.iter().map(|&(_, l)| u64::from(l)).sum();
+            let packets = total.div_ceil(self.max_packet_size.into());
+            let mut transferred = 0;
+
+            for &(addr, len) in self.segments {
+                let end = addr + u64::from(len);
+                let mut p = addr;
+
+                while p < end {
+                    let len = cmp::min(end - p, MAX_TRB_LENGTH - p % MAX_TRB_LENGTH);
+                    transferred += len;
+
+                    let remaining = packets - transferred / u64::from(self.max_packet_size);
+
+                    let mut n = Normal::new();
+                    n.set_data_buffer_pointer(p)
+                        .set_trb_transfer_length(len.try_into().unwrap())
+                        .set_td_size(cmp::min(remaining, MAX_TD_SIZE).try_into().unwrap());
+                    trbs.push(n)?;
+
+                    p += len;
+                }
+            }
+
+            if trbs.len == 0 {
+                trbs.push(Normal::new())?;
+            }
+        }
+
+        trbs.finish(self.interrupt_on_short_packet);
+
+        Ok(trbs)
+    }
+}
+
+/// The Normal TRBs of a TD.
+///
+/// This struct dereferences to a slice of up to `N` TRBs, which can be passed to
+/// [`Ring::enqueue`](super::Ring::enqueue).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Trbs<const N: usize> {
+    trbs: [Allowed; N],
+    len: usize,
+}
+impl<const N: usize> Trbs<N> {
+    fn new() -> Self {
+        Self {
+            trbs: [Normal::new().into(); N],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, n: Normal) -> Result<(), TooManyTrbs> {
+        let t = self.trbs.get_mut(self.len).ok_or(TooManyTrbs)?;
+        *t = n.into();
+        self.len += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, interrupt_on_short_packet: bool) {
+        let len = self.len;
+
+        for (i, t) in self.trbs[..len].iter_mut().enumerate() {
+            let Allowed::Normal(n) = t else {
+                unreachable!("Only Normal TRBs are pushed.");
+            };
+
+            if i + 1 == len {
+                // The last TRB completes the TD.
+                n.set_td_size(0).set_interrupt_on_completion();
+            } else {
+                n.set_chain_bit();
+            }
+
+            if interrupt_on_short_packet {
+                n.set_interrupt_on_short_packet();
+            }
+        }
+    }
+}
+impl<const N: usize> Deref for Trbs<N> {
+    type Target = [Allowed];
+
+    fn deref(&self) -> &Self::Target {
+        &self.trbs[..self.len]
+    }
+}
+impl<const N: usize> AsRef<[Allowed]> for Trbs<N> {
+    fn as_ref(&self) -> &[Allowed] {
+        self
+    }
+}
+
+/// A struct representing that a TD needs more TRBs than the capacity of [`Trbs`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
+pub struct TooManyTrbs;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn normal(t: Allowed) -> Normal {
+        let Allowed::Normal(n) = t else {
+            panic!("Unexpected TRB: {:?}", t);
+        };
+        n
+    }
+
+    #[test]
+    fn split_at_64kb_boundaries() {
+        let segments = [(0x1_f000, 0x1_2000), (0x5_0000, 0), (0x6_0000, 0x300)];
+        let trbs = NormalTransfer::new()
+            .set_max_packet_size(512)
+            .set_segments(&segments)
+            .set_interrupt_on_short_packet()
+            .build::<4>()
+            .unwrap();
+
+        let expected = [
+            (0x1_f000, 0x1000, 31),
+            (0x2_0000, 0x1_0000, 10),
+            (0x3_0000, 0x1000, 2),
+            (0x6_0000, 0x300, 0),
+        ];
+        assert_eq!(trbs.len(), expected.len());
+
+        for (i, (t, (addr, len, td_size))) in trbs.iter().zip(expected).enumerate() {
+            let n = normal(*t);
+            let last = i + 1 == expected.len();
+
+            assert_eq!(n.data_buffer_pointer(), addr);
+            assert_eq!(n.trb_transfer_length(), len);
+            assert_eq!(n.td_size(), td_size);
+            assert_eq!(n.chain_bit(), !last);
+            assert_eq!(n.interrupt_on_completion(), last);
+            assert!(n.interrupt_on_short_packet());
+        }
+
+        assert_eq!(
+            NormalTransfer::new()
+                .set_max_packet_size(512)
+                .set_segments(&segments)
+                .build::<3>(),
+            Err(TooManyTrbs)
+        );
+    }
+
+    #[test]
+    fn immediate_and_empty_data() {
+        let trbs = NormalTransfer::new()
+            .set_max_packet_size(64)
+            .set_immediate_data(&[1, 2, 3])
+            .build::<1>()
+            .unwrap();
+        let n = normal(trbs[0]);
+
+        assert!(n.immediate_data() && n.interrupt_on_completion());
+        assert_eq!(n.data_buffer_pointer(), 0x03_02_01);
+        assert_eq!(n.trb_transfer_length(), 3);
+
+        let trbs = NormalTransfer::new()
+            .set_max_packet_size(64)
+            .build::<1>()
+            .unwrap();
+        let n = normal(trbs[0]);
+
+        assert_eq!(n.trb_transfer_length(), 0);
+        assert!(!n.chain_bit() && n.interrupt_on_completion());
+    }
+}
@@ -220,11 +220,10 @@ event!(
     Type::DeviceNotification
 );
 reserved!(DeviceNotification(Type::DeviceNotification){
-    [0]0..=31;
-    [1]0..=31;
+    [0]0..=3;
     [2]0..=23;
     [3]1..=9;
-    [3]16..=31;
+    [3]16..=23;
 });
 impl DeviceNotification {
     ro_field!([0](4..=7), notification_type, "Notification Type", u8);
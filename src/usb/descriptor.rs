@@ -0,0 +1,631 @@
+//! USB descriptors.
+//!
+//! The parser does not allocate memory. Each descriptor is copied out of the buffer, except for
+//! the ones whose length is variable.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::usb::descriptor::{self, Descriptor};
+//!
+//! # let buf = [0_u8; 256];
+//! // `buf` contains the result of GET_DESCRIPTOR(Configuration).
+//! let (configuration, descriptors) =
+//!     descriptor::parse_configuration(&buf).expect("Malformed configuration descriptor.");
+//!
+//! for d in descriptors {
+//!     match d.expect("Malformed descriptor.") {
+//!         Descriptor::Interface(i) => {
+//!             // Select the driver for the interface.
+//!         }
+//!         Descriptor::Endpoint(e) => {
+//!             // Configure the endpoint.
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//! ```
+
+use super::request::DescriptorType;
+use crate::ring::trb::transfer::Direction;
+use bit_field::BitField;
+use core::convert::TryFrom;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+macro_rules! byte {
+    ($offset:literal, $method:ident, $name:literal) => {
+        #[doc = "Returns the value of the"]
+        #[doc = $name]
+        #[doc = "field."]
+        #[must_use]
+        pub fn $method(self) -> u8 {
+            self.0[$offset]
+        }
+    };
+}
+
+macro_rules! word {
+    ($offset:literal, $method:ident, $name:literal) => {
+        #[doc = "Returns the value of the"]
+        #[doc = $name]
+        #[doc = "field."]
+        #[must_use]
+        pub fn $method(self) -> u16 {
+            u16::from_le_bytes([self.0[$offset], self.0[$offset + 1]])
+        }
+    };
+}
+
+macro_rules! fixed_descriptor {
+    ($name:ident, $len:literal, $ty:ident, $full:literal) => {
+        #[doc = $full]
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+        pub struct $name([u8; $len]);
+        impl $name {
+            /// The minimum value of the `bLength` field.
+            pub const LENGTH: usize = $len;
+
+            byte!(0, length, "`bLength`");
+        }
+        impl TryFrom<&[u8]> for $name {
+            type Error = Error;
+
+            /// Copies the descriptor at the start of `buf`.
+            ///
+            /// The bytes after the first `Self::LENGTH` bytes of the descriptor are ignored.
+            fn try_from(buf: &[u8]) -> Result<Self, Self::Error> {
+                let d = split(buf)?;
+
+                if d[1] != DescriptorType::$ty as u8 {
+                    return Err(Error::UnexpectedType {
+                        expected: DescriptorType::$ty,
+                        found: d[1],
+                    });
+                }
+
+                if d.len() < $len {
+                    return Err(Error::InvalidLength {
+                        ty: d[1],
+                        length: d[0],
+                    });
+                }
+
+                let mut v = [0; $len];
+                v.copy_from_slice(&d[..$len]);
+                Ok(Self(v))
+            }
+        }
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+    };
+}
+
+/// Parses a Configuration Descriptor and the descriptors following it.
+///
+/// `buf` is the result of `GET_DESCRIPTOR(Configuration)`. The returned iterator yields the
+/// descriptors within the `wTotalLength` bytes after the Configuration Descriptor.
+///
+/// # Errors
+///
+/// This function returns an error if the first descriptor is not a valid Configuration
+/// Descriptor, if `wTotalLength` is less than `bLength`, or if `buf` is shorter than
+/// `wTotalLength`.
+pub fn parse_configuration(buf: &[u8]) -> Result<(Configuration, Descriptors<'_>), Error> {
+    let c = Configuration::try_from(buf)?;
+    let rest = children(buf, c.total_length())?;
+
+    Ok((c, rest))
+}
+
+/// Parses a BOS Descriptor and the Device Capability Descriptors following it.
+///
+/// `buf` is the result of `GET_DESCRIPTOR(BOS)`. The returned iterator yields the descriptors
+/// within the `wTotalLength` bytes after the BOS Descriptor.
+///
+/// # Errors
+///
+/// This function returns an error if the first descriptor is not a valid BOS Descriptor, if
+/// `wTotalLength` is less than `bLength`, or if `buf` is shorter than `wTotalLength`.
+pub fn parse_bos(buf: &[u8]) -> Result<(Bos, Descriptors<'_>), Error> {
+    let b = Bos::try_from(buf)?;
+    let rest = children(buf, b.total_length())?;
+
+    Ok((b, rest))
+}
+
+/// An iterator over the descriptors in a buffer.
+///
+/// The iterator yields an [`Error`] when it finds a malformed descriptor, and then stops.
+#[derive(Clone, Debug)]
+pub struct Descriptors<'a> {
+    buf: &'a [u8],
+}
+impl<'a> Descriptors<'a> {
+    /// Creates an iterator over the descriptors which are placed back to back in `buf`.
+    #[must_use]
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+impl<'a> Iterator for Descriptors<'a> {
+    type Item = Result<Descriptor<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        let r = split(self.buf).and_then(|d| {
+            self.buf = &self.buf[d.len()..];
+            Descriptor::try_from(d)
+        });
+
+        if r.is_err() {
+            self.buf = &[];
+        }
+
+        Some(r)
+    }
+}
+
+/// A USB descriptor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Descriptor<'a> {
+    /// Device Descriptor.
+    Device(Device),
+    /// Configuration Descriptor.
+    Configuration(Configuration),
+    /// Interface Descriptor.
+    Interface(Interface),
+    /// Endpoint Descriptor.
+    Endpoint(Endpoint),
+    /// `SuperSpeed` Endpoint Companion Descriptor.
+    SuperSpeedEndpointCompanion(SuperSpeedEndpointCompanion),
+    /// `SuperSpeedPlus` Isochronous Endpoint Companion Descriptor.
+    SuperSpeedPlusIsochronousEndpointCompanion(SuperSpeedPlusIsochronousEndpointCompanion),
+    /// Interface Association Descriptor.
+    InterfaceAssociation(InterfaceAssociation),
+    /// BOS Descriptor.
+    Bos(Bos),
+    /// Device Capability Descriptor.
+    DeviceCapability(DeviceCapability<'a>),
+    /// A descriptor which is not parsed by this crate, such as a class-specific one.
+    ///
+    /// The slice contains the whole descriptor including `bLength` and `bDescriptorType`.
+    Other(&'a [u8]),
+}
+impl<'a> TryFrom<&'a [u8]> for Descriptor<'a> {
+    type Error = Error;
+
+    /// Parses the descriptor at the start of `buf`.
+    fn try_from(buf: &'a [u8]) -> Result<Self, Self::Error> {
+        let d = split(buf)?;
+
+        let t = match DescriptorType::try_from(d[1]) {
+            Ok(DescriptorType::Device) => Self::Device(Device::try_from(d)?),
+            Ok(DescriptorType::Configuration) => Self::Configuration(Configuration::try_from(d)?),
+            Ok(DescriptorType::Interface) => Self::Interface(Interface::try_from(d)?),
+            Ok(DescriptorType::Endpoint) => Self::Endpoint(Endpoint::try_from(d)?),
+            Ok(DescriptorType::SuperSpeedEndpointCompanion) => {
+                Self::SuperSpeedEndpointCompanion(SuperSpeedEndpointCompanion::try_from(d)?)
+            }
+            Ok(DescriptorType::SuperSpeedPlusIsochronousEndpointCompanion) => {
+                Self::SuperSpeedPlusIsochronousEndpointCompanion(
+                    SuperSpeedPlusIsochronousEndpointCompanion::try_from(d)?,
+                )
+            }
+            Ok(DescriptorType::InterfaceAssociation) => {
+                Self::InterfaceAssociation(InterfaceAssociation::try_from(d)?)
+            }
+            Ok(DescriptorType::Bos) => Self::Bos(Bos::try_from(d)?),
+            Ok(DescriptorType::DeviceCapability) => {
+                Self::DeviceCapability(DeviceCapability::try_from(d)?)
+            }
+            _ => Self::Other(d),
+        };
+        Ok(t)
+    }
+}
+
+fixed_descriptor!(Device, 18, Device, "Device Descriptor.");
+impl Device {
+    word!(2, usb_release, "`bcdUSB`");
+    byte!(4, device_class, "`bDeviceClass`");
+    byte!(5, device_subclass, "`bDeviceSubClass`");
+    byte!(6, device_protocol, "`bDeviceProtocol`");
+    byte!(7, max_packet_size0, "`bMaxPacketSize0`");
+    word!(8, vendor_id, "`idVendor`");
+    word!(10, product_id, "`idProduct`");
+    word!(12, device_release, "`bcdDevice`");
+    byte!(14, manufacturer_index, "`iManufacturer`");
+    byte!(15, product_index, "`iProduct`");
+    byte!(16, serial_number_index, "`iSerialNumber`");
+    byte!(17, num_configurations, "`bNumConfigurations`");
+}
+impl_debug_from_methods! {
+    Device {
+        usb_release,
+        device_class,
+        device_subclass,
+        device_protocol,
+        max_packet_size0,
+        vendor_id,
+        product_id,
+        device_release,
+        manufacturer_index,
+        product_index,
+        serial_number_index,
+        num_configurations,
+    }
+}
+
+fixed_descriptor!(Configuration, 9, Configuration, "Configuration Descriptor.");
+impl Configuration {
+    word!(2, total_length, "`wTotalLength`");
+    byte!(4, num_interfaces, "`bNumInterfaces`");
+    byte!(5, configuration_value, "`bConfigurationValue`");
+    byte!(6, configuration_index, "`iConfiguration`");
+    byte!(7, attributes, "`bmAttributes`");
+    byte!(8, max_power, "`bMaxPower`");
+}
+impl_debug_from_methods! {
+    Configuration {
+        total_length,
+        num_interfaces,
+        configuration_value,
+        configuration_index,
+        attributes,
+        max_power,
+    }
+}
+
+fixed_descriptor!(Interface, 9, Interface, "Interface Descriptor.");
+impl Interface {
+    byte!(2, interface_number, "`bInterfaceNumber`");
+    byte!(3, alternate_setting, "`bAlternateSetting`");
+    byte!(4, num_endpoints, "`bNumEndpoints`");
+    byte!(5, interface_class, "`bInterfaceClass`");
+    byte!(6, interface_subclass, "`bInterfaceSubClass`");
+    byte!(7, interface_protocol, "`bInterfaceProtocol`");
+    byte!(8, interface_index, "`iInterface`");
+}
+impl_debug_from_methods! {
+    Interface {
+        interface_number,
+        alternate_setting,
+        num_endpoints,
+        interface_class,
+        interface_subclass,
+        interface_protocol,
+        interface_index,
+    }
+}
+
+fixed_descriptor!(Endpoint, 7, Endpoint, "Endpoint Descriptor.");
+impl Endpoint {
+    byte!(2, endpoint_address, "`bEndpointAddress`");
+    byte!(3, attributes, "`bmAttributes`");
+    byte!(6, interval, "`bInterval`");
+
+    /// Returns the endpoint number.
+    #[must_use]
+    pub fn number(self) -> u8 {
+        self.endpoint_address().get_bits(0..=3)
+    }
+
+    /// Returns the direction of the endpoint.
+    #[must_use]
+    pub fn direction(self) -> Direction {
+        self.endpoint_address().get_bit(7).into()
+    }
+
+    /// Returns the transfer type of the endpoint.
+    #[must_use]
+    pub fn transfer_type(self) -> TransferType {
+        FromPrimitive::from_u8(self.attributes().get_bits(0..=1))
+            .expect("All the values of the Transfer Type field are defined.")
+    }
+
+    /// Returns the maximum packet size, which is bits 0 to 10 of the `wMaxPacketSize` field.
+    #[must_use]
+    pub fn max_packet_size(self) -> u16 {
+        self.w_max_packet_size().get_bits(0..=10)
+    }
+
+    /// Returns the number of additional transaction opportunities per microframe, which is bits
+    /// 11 and 12 of the `wMaxPacketSize` field.
+    ///
+    /// This value is valid only for High-Speed Isochronous and Interrupt endpoints.
+    #[must_use]
+    pub fn additional_transactions(self) -> u8 {
+        self.w_max_packet_size()
+            .get_bits(11..=12)
+            .try_into()
+            .unwrap()
+    }
+
+    word!(4, w_max_packet_size, "`wMaxPacketSize`");
+}
+impl_debug_from_methods! {
+    Endpoint {
+        endpoint_address,
+        attributes,
+        max_packet_size,
+        additional_transactions,
+        interval,
+    }
+}
+
+fixed_descriptor!(
+    SuperSpeedEndpointCompanion,
+    6,
+    SuperSpeedEndpointCompanion,
+    "`SuperSpeed` Endpoint Companion Descriptor."
+);
+impl SuperSpeedEndpointCompanion {
+    byte!(2, max_burst, "`bMaxBurst`");
+    byte!(3, attributes, "`bmAttributes`");
+    word!(4, bytes_per_interval, "`wBytesPerInterval`");
+
+    /// Returns the `MaxStreams` field of the `bmAttributes` field.
+    ///
+    /// This value is valid only for Bulk endpoints.
+    #[must_use]
+    pub fn max_streams(self) -> u8 {
+        self.attributes().get_bits(0..=4)
+    }
+
+    /// Returns the Mult field of the `bmAttributes` field.
+    ///
+    /// This value is valid only for Isochronous endpoints.
+    #[must_use]
+    pub fn mult(self) -> u8 {
+        self.attributes().get_bits(0..=1)
+    }
+
+    /// Returns the `SSP ISO Companion` bit of the `bmAttributes` field.
+    ///
+    /// If this bit is set, a `SuperSpeedPlus` Isochronous Endpoint Companion Descriptor follows this
+    /// descriptor.
+    #[must_use]
+    pub fn ssp_isochronous_companion(self) -> bool {
+        self.attributes().get_bit(7)
+    }
+}
+impl_debug_from_methods! {
+    SuperSpeedEndpointCompanion {
+        max_burst,
+        attributes,
+        bytes_per_interval,
+    }
+}
+
+fixed_descriptor!(
+    SuperSpeedPlusIsochronousEndpointCompanion,
+    8,
+    SuperSpeedPlusIsochronousEndpointCompanion,
+    "`SuperSpeedPlus` Isochronous Endpoint Companion Descriptor."
+);
+impl SuperSpeedPlusIsochronousEndpointCompanion {
+    /// Returns the value of the `dwBytesPerInterval` field.
+    #[must_use]
+    pub fn bytes_per_interval(self) -> u32 {
+        u32::from_le_bytes([self.0[4], self.0[5], self.0[6], self.0[7]])
+    }
+}
+impl_debug_from_methods! {
+    SuperSpeedPlusIsochronousEndpointCompanion {
+        bytes_per_interval,
+    }
+}
+
+fixed_descriptor!(
+    InterfaceAssociation,
+    8,
+    InterfaceAssociation,
+    "Interface Association Descriptor."
+);
+impl InterfaceAssociation {
+    byte!(2, first_interface, "`bFirstInterface`");
+    byte!(3, interface_count, "`bInterfaceCount`");
+    byte!(4, function_class, "`bFunctionClass`");
+    byte!(5, function_subclass, "`bFunctionSubClass`");
+    byte!(6, function_protocol, "`bFunctionProtocol`");
+    byte!(7, function_index, "`iFunction`");
+}
+impl_debug_from_methods! {
+    InterfaceAssociation {
+        first_interface,
+        interface_count,
+        function_class,
+        function_subclass,
+        function_protocol,
+        function_index,
+    }
+}
+
+fixed_descriptor!(Bos, 5, Bos, "BOS Descriptor.");
+impl Bos {
+    word!(2, total_length, "`wTotalLength`");
+    byte!(4, num_device_capabilities, "`bNumDeviceCaps`");
+}
+impl_debug_from_methods! {
+    Bos {
+        total_length,
+        num_device_capabilities,
+    }
+}
+
+/// Device Capability Descriptor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DeviceCapability<'a>(&'a [u8]);
+impl<'a> DeviceCapability<'a> {
+    /// Returns the value of the `bDevCapabilityType` field.
+    #[must_use]
+    pub fn capability_type(self) -> u8 {
+        self.0[2]
+    }
+
+    /// Returns the capability-dependent bytes following the `bDevCapabilityType` field.
+    #[must_use]
+    pub fn data(self) -> &'a [u8] {
+        &self.0[3..]
+    }
+}
+impl<'a> TryFrom<&'a [u8]> for DeviceCapability<'a> {
+    type Error = Error;
+
+    /// Parses the descriptor at the start of `buf`.
+    fn try_from(buf: &'a [u8]) -> Result<Self, Self::Error> {
+        let d = split(buf)?;
+
+        if d[1] != DescriptorType::DeviceCapability as u8 {
+            return Err(Error::UnexpectedType {
+                expected: DescriptorType::DeviceCapability,
+                found: d[1],
+            });
+        }
+
+        if d.len() < 3 {
+            return Err(Error::InvalidLength {
+                ty: d[1],
+                length: d[0],
+            });
+        }
+
+        Ok(Self(d))
+    }
+}
+
+/// The transfer type of an endpoint.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum TransferType {
+    /// Control.
+    Control = 0,
+    /// Isochronous.
+    Isochronous = 1,
+    /// Bulk.
+    Bulk = 2,
+    /// Interrupt.
+    Interrupt = 3,
+}
+
+/// An error which occurs while parsing descriptors.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Error {
+    /// The buffer ends in the middle of a descriptor, or is shorter than `wTotalLength`.
+    Truncated,
+    /// The `bLength` field is less than the length of the descriptor type.
+    InvalidLength {
+        /// The value of the `bDescriptorType` field.
+        ty: u8,
+        /// The value of the `bLength` field.
+        length: u8,
+    },
+    /// The `wTotalLength` field is less than the length of the descriptor itself.
+    InvalidTotalLength(u16),
+    /// The descriptor is not of the expected type.
+    UnexpectedType {
+        /// The expected type.
+        expected: DescriptorType,
+        /// The value of the `bDescriptorType` field.
+        found: u8,
+    },
+}
+
+/// Returns the first descriptor in `buf`.
+fn split(buf: &[u8]) -> Result<&[u8], Error> {
+    let [length, ty, ..] = *buf else {
+        return Err(Error::Truncated);
+    };
+
+    if length < 2 {
+        return Err(Error::InvalidLength { ty, length });
+    }
+
+    buf.get(..length.into()).ok_or(Error::Truncated)
+}
+
+/// Returns the descriptors following the first one within `total_length` bytes.
+fn children(buf: &[u8], total_length: u16) -> Result<Descriptors<'_>, Error> {
+    let head = usize::from(buf[0]);
+    let total = usize::from(total_length);
+
+    if total < head {
+        return Err(Error::InvalidTotalLength(total_length));
+    }
+
+    let rest = buf.get(head..total).ok_or(Error::Truncated)?;
+    Ok(Descriptors::new(rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A SuperSpeed mass storage device with one Bulk-In and one Bulk-Out endpoint.
+    const CONFIGURATION: [u8; 44] = [
+        9, 2, 44, 0, 1, 1, 0, 0x80, 50, // Configuration
+        9, 4, 0, 0, 2, 8, 6, 0x50, 0, // Interface
+        7, 5, 0x81, 2, 0, 4, 0, // Endpoint
+        6, 48, 15, 0, 0, 0, // SuperSpeed Endpoint Companion
+        7, 5, 0x02, 2, 0, 4, 0, // Endpoint
+        6, 48, 15, 0, 0, 0, // SuperSpeed Endpoint Companion
+    ];
+
+    #[test]
+    fn walk_configuration() {
+        let (configuration, mut descriptors) = parse_configuration(&CONFIGURATION).unwrap();
+        assert_eq!(configuration.total_length(), 44);
+        assert_eq!(configuration.configuration_value(), 1);
+
+        let Some(Ok(Descriptor::Interface(interface))) = descriptors.next() else {
+            panic!("Expected an Interface Descriptor.");
+        };
+        assert_eq!(interface.interface_class(), 8);
+
+        let Some(Ok(Descriptor::Endpoint(endpoint))) = descriptors.next() else {
+            panic!("Expected an Endpoint Descriptor.");
+        };
+        assert_eq!(endpoint.number(), 1);
+        assert_eq!(endpoint.direction(), Direction::In);
+        assert_eq!(endpoint.transfer_type(), TransferType::Bulk);
+        assert_eq!(endpoint.max_packet_size(), 1024);
+
+        let Some(Ok(Descriptor::SuperSpeedEndpointCompanion(companion))) = descriptors.next()
+        else {
+            panic!("Expected a SuperSpeed Endpoint Companion Descriptor.");
+        };
+        assert_eq!(companion.max_burst(), 15);
+
+        assert_eq!(descriptors.count(), 2);
+    }
+
+    #[test]
+    fn malformed_descriptors() {
+        assert_eq!(
+            parse_configuration(&CONFIGURATION[..40]).unwrap_err(),
+            Error::Truncated
+        );
+
+        let mut buf = CONFIGURATION;
+        buf[18] = 6;
+        let mut descriptors = parse_configuration(&buf).unwrap().1;
+        assert_eq!(
+            descriptors.nth(1),
+            Some(Err(Error::InvalidLength { ty: 5, length: 6 }))
+        );
+        assert!(descriptors.next().is_none());
+
+        buf[18] = 0;
+        assert_eq!(
+            parse_configuration(&buf).unwrap().1.nth(1),
+            Some(Err(Error::InvalidLength { ty: 5, length: 0 }))
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Endpoint Context configuration.
+//!
+//! The values are calculated as described in sections 4.14 and 6.2.3 of the xHCI specification.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use xhci::context::{self, InputHandler};
+//! use xhci::usb::{descriptor::Endpoint, endpoint, Speed};
+//!
+//! # let e: Endpoint = unimplemented!();
+//! let mut input = context::Input::new_32byte();
+//! let dci = endpoint::dci(e.endpoint_address());
+//!
+//! input.control_mut().set_add_context_flag(dci.into());
+//! endpoint::Config::new(e, Speed::High).apply(input.device_mut().endpoint_mut(dci.into()));
+//! ```
+
+use super::descriptor::{
+    Endpoint, SuperSpeedEndpointCompanion, SuperSpeedPlusIsochronousEndpointCompanion, TransferType,
+};
+use super::Speed;
+use crate::context::{EndpointHandler, EndpointType};
+use crate::ring::trb::transfer::Direction;
+use bit_field::BitField;
+use core::convert::TryInto;
+
+/// Returns the Device Context Index of the endpoint whose `bEndpointAddress` is
+/// `endpoint_address`.
+///
+/// The Default Control Endpoint (endpoint 0) has DCI 1 regardless of the direction bit. Use
+/// [`Config::dci`] for the other Control endpoints, as the index cannot be calculated only from the
+/// address.
+#[must_use]
+pub fn dci(endpoint_address: u8) -> u8 {
+    let number = endpoint_address.get_bits(0..=3);
+
+    if number == 0 {
+        1
+    } else {
+        number * 2 + u8::from(endpoint_address.get_bit(7))
+    }
+}
+
+/// The values of an Endpoint Context derived from the descriptors of the endpoint.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Config {
+    endpoint: Endpoint,
+    speed: Speed,
+    companion: Option<SuperSpeedEndpointCompanion>,
+    ssp_isochronous_companion: Option<SuperSpeedPlusIsochronousEndpointCompanion>,
+}
+impl Config {
+    /// Creates a new configuration of the endpoint described by `endpoint`.
+    ///
+    /// `speed` is the speed of the port the device is connected to.
+    #[must_use]
+    pub fn new(endpoint: Endpoint, speed: Speed) -> Self {
+        Self {
+            endpoint,
+            speed,
+            companion: None,
+            ssp_isochronous_companion: None,
+        }
+    }
+
+    /// Sets the `SuperSpeed` Endpoint Companion Descriptor following the Endpoint Descriptor.
+    ///
+    /// This descriptor is used only if the speed is `SuperSpeed` or `SuperSpeedPlus`.
+    pub fn set_companion(&mut self, c: SuperSpeedEndpointCompanion) -> &mut Self {
+        self.companion = Some(c);
+        self
+    }
+
+    /// Sets the `SuperSpeedPlus` Isochronous Endpoint Companion Descriptor following the
+    /// `SuperSpeed` Endpoint Companion Descriptor.
+    pub fn set_ssp_isochronous_companion(
+        &mut self,
+        c: SuperSpeedPlusIsochronousEndpointCompanion,
+    ) -> &mut Self {
+        self.ssp_isochronous_companion = Some(c);
+        self
+    }
+
+    /// Writes the values to `cx`.
+    ///
+    /// This method sets the Endpoint Type, Interval, Mult, Max Burst Size, Max Packet Size, Error
+    /// Count, Average TRB Length, and Max Endpoint Service Time Interval Payload fields. The other
+    /// fields, such as the TR Dequeue Pointer, are not modified.
+    pub fn apply(&self, cx: &mut dyn EndpointHandler) {
+        let payload = self.max_esit_payload();
+
+        cx.set_endpoint_type(self.endpoint_type());
+        cx.set_interval(self.interval());
+        cx.set_mult(self.mult());
+        cx.set_max_burst_size(self.max_burst_size());
+        cx.set_max_packet_size(self.max_packet_size());
+        cx.set_error_count(self.error_count());
+        cx.set_average_trb_length(self.average_trb_length());
+        cx.set_max_endpoint_service_time_interval_payload_high(
+            payload.get_bits(16..=23).try_into().unwrap(),
+        );
+        cx.set_max_endpoint_service_time_interval_payload_low(
+            payload.get_bits(0..=15).try_into().unwrap(),
+        );
+    }
+
+    /// Returns the Device Context Index of the endpoint.
+    #[must_use]
+    pub fn dci(&self) -> u8 {
+        if self.endpoint.transfer_type() == TransferType::Control {
+            self.endpoint.number() * 2 + 1
+        } else {
+            dci(self.endpoint.endpoint_address())
+        }
+    }
+
+    /// Returns the value of the Endpoint Type field.
+    #[must_use]
+    pub fn endpoint_type(&self) -> EndpointType {
+        let d = self.endpoint.direction();
+
+        match (self.endpoint.transfer_type(), d) {
+            (TransferType::Control, _) => EndpointType::Control,
+            (TransferType::Isochronous, Direction::Out) => EndpointType::IsochOut,
+            (TransferType::Isochronous, Direction::In) => EndpointType::IsochIn,
+            (TransferType::Bulk, Direction::Out) => EndpointType::BulkOut,
+            (TransferType::Bulk, Direction::In) => EndpointType::BulkIn,
+            (TransferType::Interrupt, Direction::Out) => EndpointType::InterruptOut,
+            (TransferType::Interrupt, Direction::In) => EndpointType::InterruptIn,
+        }
+    }
+
+    /// Returns the value of the Interval field.
+    ///
+    /// The service interval of the endpoint is `125us * 2^Interval`. The value is 0 for the
+    /// Control and Bulk endpoints.
+    #[must_use]
+    pub fn interval(&self) -> u8 {
+        let b = self.endpoint.interval();
+
+        match (self.endpoint.transfer_type(), self.speed) {
+            (TransferType::Control | TransferType::Bulk, _) => 0,
+            // `bInterval` is in milliseconds.
+            (TransferType::Interrupt, Speed::Low | Speed::Full) => {
+                let microframes = u16::from(b.max(1)) * 8;
+
+                microframes.ilog2().clamp(3, 10).try_into().unwrap()
+            }
+            // `bInterval` is the exponent of the number of frames plus 1.
+            (TransferType::Isochronous, Speed::Low | Speed::Full) => b.clamp(1, 16) + 2,
+            // `bInterval` is the exponent of the number of microframes plus 1.
+            _ => b.clamp(1, 16) - 1,
+        }
+    }
+
+    /// Returns the value of the Mult field.
+    ///
+    /// The value is not 0 only for `SuperSpeed` Isochronous endpoints.
+    #[must_use]
+    pub fn mult(&self) -> u8 {
+        match self.companion {
+            Some(c)
+                if self.speed.is_super_speed()
+                    && self.endpoint.transfer_type() == TransferType::Isochronous
+                    && !c.ssp_isochronous_companion() =>
+            {
+                c.mult()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns the value of the Max Burst Size field.
+    #[must_use]
+    pub fn max_burst_size(&self) -> u8 {
+        if self.speed.is_super_speed() {
+            self.companion
+                .map_or(0, SuperSpeedEndpointCompanion::max_burst)
+        } else if self.speed == Speed::High && self.is_periodic() {
+            self.endpoint.additional_transactions()
+        } else {
+            0
+        }
+    }
+
+    /// Returns the value of the Max Packet Size field.
+    #[must_use]
+    pub fn max_packet_size(&self) -> u16 {
+        self.endpoint.max_packet_size()
+    }
+
+    /// Returns the value of the Error Count field.
+    ///
+    /// The value is 0 for Isochronous endpoints, and 3 for the others.
+    #[must_use]
+    pub fn error_count(&self) -> u8 {
+        if self.endpoint.transfer_type() == TransferType::Isochronous {
+            0
+        } else {
+            3
+        }
+    }
+
+    /// Returns the value of the Average TRB Length field.
+    ///
+    /// The values recommended in section 4.14.1.1 of the xHCI specification are used.
+    #[must_use]
+    pub fn average_trb_length(&self) -> u16 {
+        match self.endpoint.transfer_type() {
+            TransferType::Control => 8,
+            TransferType::Interrupt => 1024,
+            TransferType::Bulk | TransferType::Isochronous => 3072,
+        }
+    }
+
+    /// Returns the Max Endpoint Service Time Interval Payload, which is split into the High and
+    /// the Low fields.
+    ///
+    /// The value is 0 for the Control and Bulk endpoints.
+    #[must_use]
+    pub fn max_esit_payload(&self) -> u32 {
+        if !self.is_periodic() {
+            return 0;
+        }
+
+        let max_packet_size = u32::from(self.max_packet_size());
+
+        if self.speed.is_super_speed() {
+            match (self.ssp_isochronous_companion, self.companion) {
+                (Some(s), Some(c)) if c.ssp_isochronous_companion() => s.bytes_per_interval(),
+                (_, Some(c)) => c.bytes_per_interval().into(),
+                (_, None) => max_packet_size,
+            }
+        } else if self.speed == Speed::High {
+            max_packet_size * (u32::from(self.endpoint.additional_transactions()) + 1)
+        } else {
+            max_packet_size
+        }
+    }
+
+    fn is_periodic(&self) -> bool {
+        matches!(
+            self.endpoint.transfer_type(),
+            TransferType::Isochronous | TransferType::Interrupt
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::TryFrom;
+
+    fn endpoint(raw: [u8; 7]) -> Endpoint {
+        Endpoint::try_from(&raw[..]).unwrap()
+    }
+
+    #[test]
+    fn device_context_index() {
+        assert_eq!(dci(0x00), 1);
+        assert_eq!(dci(0x80), 1);
+        assert_eq!(dci(0x01), 2);
+        assert_eq!(dci(0x81), 3);
+        assert_eq!(dci(0x8f), 31);
+    }
+
+    #[test]
+    fn full_speed_interrupt() {
+        let c = Config::new(endpoint([7, 5, 0x81, 3, 8, 0, 10]), Speed::Full);
+
+        assert_eq!(c.endpoint_type(), EndpointType::InterruptIn);
+        assert_eq!(c.interval(), 6);
+        assert_eq!(c.max_esit_payload(), 8);
+        assert_eq!(c.error_count(), 3);
+    }
+
+    #[test]
+    fn high_speed_isoch() {
+        let c = Config::new(endpoint([7, 5, 0x02, 1, 0x00, 0x14, 1]), Speed::High);
+
+        assert_eq!(c.endpoint_type(), EndpointType::IsochOut);
+        assert_eq!(c.interval(), 0);
+        assert_eq!(c.max_burst_size(), 2);
+        assert_eq!(c.max_packet_size(), 1024);
+        assert_eq!(c.max_esit_payload(), 3072);
+        assert_eq!(c.error_count(), 0);
+    }
+
+    #[test]
+    fn super_speed_isoch() {
+        let companion =
+            SuperSpeedEndpointCompanion::try_from(&[6, 48, 3, 2, 0x00, 0x30][..]).unwrap();
+        let c = *Config::new(endpoint([7, 5, 0x83, 1, 0x00, 0x04, 4]), Speed::SuperSpeed)
+            .set_companion(companion);
+
+        assert_eq!(c.dci(), 7);
+        assert_eq!(c.interval(), 3);
+        assert_eq!(c.mult(), 2);
+        assert_eq!(c.max_burst_size(), 3);
+        assert_eq!(c.max_esit_payload(), 0x3000);
+    }
+}
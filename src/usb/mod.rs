@@ -0,0 +1,27 @@
+//! USB-level structures which are not defined by the xHCI specification.
+
+pub mod descriptor;
+pub mod endpoint;
+pub mod request;
+
+/// The speed of a USB device.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum Speed {
+    /// Low-Speed (1.5 Mb/s).
+    Low,
+    /// Full-Speed (12 Mb/s).
+    Full,
+    /// High-Speed (480 Mb/s).
+    High,
+    /// `SuperSpeed` (Gen1 x1).
+    SuperSpeed,
+    /// `SuperSpeedPlus` (Gen2 x1, Gen1 x2, or Gen2 x2).
+    SuperSpeedPlus,
+}
+impl Speed {
+    /// Returns `true` if the speed is `SuperSpeed` or `SuperSpeedPlus`.
+    #[must_use]
+    pub fn is_super_speed(self) -> bool {
+        matches!(self, Self::SuperSpeed | Self::SuperSpeedPlus)
+    }
+}
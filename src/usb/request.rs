@@ -0,0 +1,411 @@
+//! USB device requests.
+//!
+//! # Examples
+//!
+//! ```
+//! use xhci::ring::trb::transfer::SetupStage;
+//! use xhci::usb::request::{DescriptorType, SetupPacket};
+//!
+//! let setup: SetupStage = SetupPacket::get_descriptor(DescriptorType::Device, 0, 18).into();
+//!
+//! assert_eq!(setup.request_type(), 0x80);
+//! assert_eq!(setup.request(), 6);
+//! assert_eq!(setup.value(), 0x0100);
+//! ```
+
+use crate::ring::trb::transfer::{Direction, SetupStage, TransferType};
+use bit_field::BitField;
+use core::convert::TryFrom;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+
+/// The setup packet of a control transfer.
+#[derive(Copy, Clone, Debug, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SetupPacket {
+    /// The `bmRequestType` field.
+    pub request_type: RequestType,
+    /// The `bRequest` field.
+    pub request: u8,
+    /// The `wValue` field.
+    pub value: u16,
+    /// The `wIndex` field.
+    pub index: u16,
+    /// The `wLength` field.
+    pub length: u16,
+}
+impl SetupPacket {
+    /// Creates a `GET_DESCRIPTOR` request.
+    ///
+    /// `index` is the descriptor index, and `length` is the number of bytes to return. The
+    /// `wIndex` field is set to 0. Set it to the Language ID to get a string descriptor.
+    #[must_use]
+    pub fn get_descriptor(ty: DescriptorType, index: u8, length: u16) -> Self {
+        Self {
+            request_type: RequestType::new(Direction::In, Type::Standard, Recipient::Device),
+            request: StandardRequest::GetDescriptor as _,
+            value: u16::from_be_bytes([ty as _, index]),
+            index: 0,
+            length,
+        }
+    }
+
+    /// Creates a `SET_ADDRESS` request.
+    ///
+    /// Note that the xHC issues this request on behalf of the software when it executes an Address
+    /// Device Command.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `address > 127`.
+    #[must_use]
+    pub fn set_address(address: u8) -> Self {
+        assert!(address <= 127, "The device address must be less than 128.");
+
+        Self::standard_out(Recipient::Device, StandardRequest::SetAddress)
+            .with_value(address.into())
+    }
+
+    /// Creates a `SET_CONFIGURATION` request.
+    #[must_use]
+    pub fn set_configuration(configuration_value: u8) -> Self {
+        Self::standard_out(Recipient::Device, StandardRequest::SetConfiguration)
+            .with_value(configuration_value.into())
+    }
+
+    /// Creates a `CLEAR_FEATURE(ENDPOINT_HALT)` request.
+    ///
+    /// `endpoint_address` is the `bEndpointAddress` field of the endpoint descriptor.
+    #[must_use]
+    pub fn clear_endpoint_halt(endpoint_address: u8) -> Self {
+        let mut p = Self::standard_out(Recipient::Endpoint, StandardRequest::ClearFeature)
+            .with_value(FeatureSelector::EndpointHalt as _);
+        p.index = endpoint_address.into();
+        p
+    }
+
+    /// Creates a `SET_INTERFACE` request.
+    #[must_use]
+    pub fn set_interface(interface: u8, alternate_setting: u8) -> Self {
+        let mut p = Self::standard_out(Recipient::Interface, StandardRequest::SetInterface)
+            .with_value(alternate_setting.into());
+        p.index = interface.into();
+        p
+    }
+
+    /// Creates a `GET_STATUS` request.
+    ///
+    /// `index` is 0 for a device, the interface number for an interface, or the endpoint address
+    /// for an endpoint.
+    #[must_use]
+    pub fn get_status(recipient: Recipient, index: u16) -> Self {
+        Self {
+            request_type: RequestType::new(Direction::In, Type::Standard, recipient),
+            request: StandardRequest::GetStatus as _,
+            value: 0,
+            index,
+            length: 2,
+        }
+    }
+
+    /// Creates a `SET_SEL` request.
+    ///
+    /// The Data Stage must contain the 6-byte exit latency values.
+    #[must_use]
+    pub fn set_sel() -> Self {
+        let mut p = Self::standard_out(Recipient::Device, StandardRequest::SetSel);
+        p.length = 6;
+        p
+    }
+
+    /// Creates a `SET_ISOCH_DELAY` request.
+    ///
+    /// `delay` is in nanoseconds.
+    #[must_use]
+    pub fn set_isoch_delay(delay: u16) -> Self {
+        Self::standard_out(Recipient::Device, StandardRequest::SetIsochDelay).with_value(delay)
+    }
+
+    /// Returns the Transfer Type of the Setup Stage TRB which sends this packet.
+    #[must_use]
+    pub fn transfer_type(&self) -> TransferType {
+        if self.length == 0 {
+            TransferType::No
+        } else {
+            match self.request_type.direction() {
+                Direction::In => TransferType::In,
+                Direction::Out => TransferType::Out,
+            }
+        }
+    }
+
+    fn standard_out(recipient: Recipient, request: StandardRequest) -> Self {
+        Self {
+            request_type: RequestType::new(Direction::Out, Type::Standard, recipient),
+            request: request as _,
+            ..Self::default()
+        }
+    }
+
+    fn with_value(self, value: u16) -> Self {
+        Self { value, ..self }
+    }
+}
+impl From<SetupPacket> for SetupStage {
+    /// Creates a Setup Stage TRB which sends the packet. The Transfer Type field is also set.
+    fn from(p: SetupPacket) -> Self {
+        *SetupStage::new()
+            .set_request_type(p.request_type.into())
+            .set_request(p.request)
+            .set_value(p.value)
+            .set_index(p.index)
+            .set_length(p.length)
+            .set_transfer_type(p.transfer_type())
+    }
+}
+impl From<SetupStage> for SetupPacket {
+    fn from(s: SetupStage) -> Self {
+        Self {
+            request_type: s.request_type().into(),
+            request: s.request(),
+            value: s.value(),
+            index: s.index(),
+            length: s.length(),
+        }
+    }
+}
+impl From<SetupPacket> for [u8; 8] {
+    fn from(p: SetupPacket) -> Self {
+        let mut b = [0; 8];
+        b[0] = p.request_type.into();
+        b[1] = p.request;
+        b[2..4].copy_from_slice(&p.value.to_le_bytes());
+        b[4..6].copy_from_slice(&p.index.to_le_bytes());
+        b[6..8].copy_from_slice(&p.length.to_le_bytes());
+        b
+    }
+}
+impl From<[u8; 8]> for SetupPacket {
+    fn from(b: [u8; 8]) -> Self {
+        Self {
+            request_type: b[0].into(),
+            request: b[1],
+            value: u16::from_le_bytes([b[2], b[3]]),
+            index: u16::from_le_bytes([b[4], b[5]]),
+            length: u16::from_le_bytes([b[6], b[7]]),
+        }
+    }
+}
+
+/// The `bmRequestType` field.
+#[repr(transparent)]
+#[derive(Copy, Clone, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RequestType(u8);
+impl RequestType {
+    /// Creates a new `bmRequestType` value.
+    #[must_use]
+    pub fn new(direction: Direction, ty: Type, recipient: Recipient) -> Self {
+        let mut v = 0;
+        v.set_bit(7, direction.into());
+        v.set_bits(5..=6, ty as _);
+        v.set_bits(0..=4, recipient as _);
+        Self(v)
+    }
+
+    /// Returns the direction of the Data Stage.
+    #[must_use]
+    pub fn direction(self) -> Direction {
+        self.0.get_bit(7).into()
+    }
+
+    /// Returns the type of the request.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an [`Err`] value with the Type field if it contains 3 which is
+    /// reserved.
+    pub fn ty(self) -> Result<Type, u8> {
+        let t = self.0.get_bits(5..=6);
+        FromPrimitive::from_u8(t).ok_or(t)
+    }
+
+    /// Returns the recipient of the request.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an [`Err`] value with the Recipient field if it contains a reserved
+    /// value.
+    pub fn recipient(self) -> Result<Recipient, u8> {
+        let r = self.0.get_bits(0..=4);
+        FromPrimitive::from_u8(r).ok_or(r)
+    }
+}
+impl From<u8> for RequestType {
+    fn from(v: u8) -> Self {
+        Self(v)
+    }
+}
+impl From<RequestType> for u8 {
+    fn from(t: RequestType) -> Self {
+        t.0
+    }
+}
+impl_debug_from_methods! {
+    RequestType {
+        direction,
+        ty,
+        recipient,
+    }
+}
+
+/// The type of a request.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum Type {
+    /// Standard.
+    Standard = 0,
+    /// Class.
+    Class = 1,
+    /// Vendor.
+    Vendor = 2,
+}
+
+/// The recipient of a request.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum Recipient {
+    /// Device.
+    Device = 0,
+    /// Interface.
+    Interface = 1,
+    /// Endpoint.
+    Endpoint = 2,
+    /// Other.
+    Other = 3,
+}
+
+/// The `bRequest` codes of the standard requests.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum StandardRequest {
+    /// `GET_STATUS`
+    GetStatus = 0,
+    /// `CLEAR_FEATURE`
+    ClearFeature = 1,
+    /// `SET_FEATURE`
+    SetFeature = 3,
+    /// `SET_ADDRESS`
+    SetAddress = 5,
+    /// `GET_DESCRIPTOR`
+    GetDescriptor = 6,
+    /// `SET_DESCRIPTOR`
+    SetDescriptor = 7,
+    /// `GET_CONFIGURATION`
+    GetConfiguration = 8,
+    /// `SET_CONFIGURATION`
+    SetConfiguration = 9,
+    /// `GET_INTERFACE`
+    GetInterface = 10,
+    /// `SET_INTERFACE`
+    SetInterface = 11,
+    /// `SYNCH_FRAME`
+    SynchFrame = 12,
+    /// `SET_SEL`
+    SetSel = 48,
+    /// `SET_ISOCH_DELAY`
+    SetIsochDelay = 49,
+}
+
+/// The standard feature selectors.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum FeatureSelector {
+    /// `ENDPOINT_HALT`, or `FUNCTION_SUSPEND` if the recipient is an interface.
+    EndpointHalt = 0,
+    /// `DEVICE_REMOTE_WAKEUP`
+    DeviceRemoteWakeup = 1,
+    /// `TEST_MODE`
+    TestMode = 2,
+    /// `U1_ENABLE`
+    U1Enable = 48,
+    /// `U2_ENABLE`
+    U2Enable = 49,
+    /// `LTM_ENABLE`
+    LtmEnable = 50,
+}
+
+/// Descriptor Type.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, FromPrimitive)]
+pub enum DescriptorType {
+    /// Device.
+    Device = 1,
+    /// Configuration.
+    Configuration = 2,
+    /// String.
+    String = 3,
+    /// Interface.
+    Interface = 4,
+    /// Endpoint.
+    Endpoint = 5,
+    /// Device Qualifier.
+    DeviceQualifier = 6,
+    /// Other Speed Configuration.
+    OtherSpeedConfiguration = 7,
+    /// Interface Power.
+    InterfacePower = 8,
+    /// OTG.
+    Otg = 9,
+    /// Debug.
+    Debug = 10,
+    /// Interface Association.
+    InterfaceAssociation = 11,
+    /// BOS.
+    Bos = 15,
+    /// Device Capability.
+    DeviceCapability = 16,
+    /// `SuperSpeed` Endpoint Companion.
+    SuperSpeedEndpointCompanion = 48,
+    /// `SuperSpeedPlus` Isochronous Endpoint Companion.
+    SuperSpeedPlusIsochronousEndpointCompanion = 49,
+}
+impl TryFrom<u8> for DescriptorType {
+    type Error = u8;
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        FromPrimitive::from_u8(x).ok_or(x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clear_endpoint_halt() {
+        let p = SetupPacket::clear_endpoint_halt(0x81);
+
+        assert_eq!(u8::from(p.request_type), 0x02);
+        assert_eq!(
+            <[u8; 8]>::from(p),
+            [0x02, 0x01, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn setup_stage_round_trip() {
+        let p = SetupPacket::get_descriptor(DescriptorType::Configuration, 1, 255);
+        let s = SetupStage::from(p);
+
+        assert_eq!(s.transfer_type(), TransferType::In);
+        assert_eq!(SetupPacket::from(s), p);
+        assert_eq!(p.request_type.recipient(), Ok(Recipient::Device));
+        assert_eq!(p.request_type.ty(), Ok(Type::Standard));
+    }
+
+    #[test]
+    fn reserved_request_type() {
+        let t = RequestType::from(0xff);
+
+        assert_eq!(t.direction(), Direction::In);
+        assert_eq!(t.ty(), Err(3));
+        assert_eq!(t.recipient(), Err(31));
+        assert_eq!(
+            alloc::format!("{t:?}"),
+            "RequestType { direction: In, ty: Err(3), recipient: Err(31) }"
+        );
+    }
+}